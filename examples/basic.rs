@@ -4,7 +4,7 @@
 extern crate qemu;
 
 use qemu::machine::{Processors, Memory};
-use qemu::display::{Display, Vnc, Vga};
+use qemu::display::{Display, Vga};
 
 fn main() {
     let builder = qemu::Builder::new("qemu-system-x86_64").unwrap()
@@ -13,5 +13,5 @@ fn main() {
         .set(Display::Sdl)
         .set(Vga::Std);
 
-    let emulator = builder.start().unwrap();
+    let _emulator = builder.start().unwrap();
 }
@@ -0,0 +1,63 @@
+//! QEMU audio options.
+
+/// Host audio backend used to play and record the guest's sound.
+pub enum Backend {
+    None,
+    PulseAudio,
+    Alsa,
+    Sdl,
+    /// Redirect the sound to the SPICE client. Requires a `display::Display::Spice` display.
+    Spice,
+}
+
+/// Sound card model emulated for the guest.
+pub enum Card {
+    /// Intel High Definition Audio, supported by most modern guests.
+    Hda,
+    Ac97,
+    Sb16,
+}
+
+/// Represents a sound card attached to the machine, along with the host audio backend it uses.
+pub struct Audio {
+    backend: Backend,
+    card: Card,
+}
+
+impl Audio {
+    /// Create a new sound card of the specified model, using the specified audio backend.
+    pub fn new(backend: Backend, card: Card) -> Audio {
+        Audio {
+            backend,
+            card,
+        }
+    }
+}
+
+impl super::IntoArguments for Audio {
+    fn into_arguments(self) -> Vec<String> {
+        let backend = match self.backend {
+            Backend::None => "none",
+            Backend::PulseAudio => "pa",
+            Backend::Alsa => "alsa",
+            Backend::Sdl => "sdl",
+            Backend::Spice => "spice",
+        };
+
+        let mut args = vec![String::from("-audiodev"), format!("{},id=snd0", backend)];
+
+        // HDA codecs sit on an Intel HDA controller, the other cards are standalone devices.
+        let devices: &[&str] = match self.card {
+            Card::Hda => &["intel-hda", "hda-duplex,audiodev=snd0"],
+            Card::Ac97 => &["AC97,audiodev=snd0"],
+            Card::Sb16 => &["sb16,audiodev=snd0"],
+        };
+
+        for device in devices {
+            args.push(String::from("-device"));
+            args.push(device.to_string());
+        }
+
+        args
+    }
+}
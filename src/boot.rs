@@ -0,0 +1,154 @@
+//! QEMU boot options.
+
+use error::{InitError, Result};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Represents the settings used to boot a Linux kernel directly, without a bootloader.
+pub struct DirectBoot {
+    kernel: PathBuf,
+    initrd: Option<PathBuf>,
+    append: Option<String>,
+}
+
+impl DirectBoot {
+    /// Boot the kernel image at the specified path.
+    pub fn new<P: Into<PathBuf>>(kernel: P) -> DirectBoot {
+        DirectBoot {
+            kernel: kernel.into(),
+            initrd: None,
+            append: None,
+        }
+    }
+
+    /// Set the initial ram disk loaded along with the kernel.
+    pub fn set_initrd<P: Into<PathBuf>>(mut self, initrd: P) -> Self {
+        self.initrd = Some(initrd.into());
+        self
+    }
+
+    /// Set the kernel command line.
+    pub fn set_append<S: Into<String>>(mut self, cmdline: S) -> Self {
+        self.append = Some(cmdline.into());
+        self
+    }
+}
+
+impl super::IntoArguments for DirectBoot {
+    fn into_arguments(self) -> Vec<String> {
+        let mut args = vec![String::from("-kernel"), self.kernel.display().to_string()];
+
+        if let Some(initrd) = self.initrd {
+            args.push(String::from("-initrd"));
+            args.push(initrd.display().to_string());
+        }
+
+        if let Some(append) = self.append {
+            args.push(String::from("-append"));
+            args.push(append);
+        }
+
+        args
+    }
+}
+
+/// Represents the firmware the machine boots with.
+pub enum Firmware {
+    /// A BIOS image, loaded with `-bios`.
+    Bios(PathBuf),
+
+    /// A UEFI firmware split in two pflash drives, as shipped by OVMF: the read-only code, and
+    /// the writable variable store. The variable store is modified by the guest, so each machine
+    /// needs its own copy. QEMU maps the pflash drives by unit number, the code must be unit 0
+    /// and the variables unit 1.
+    Pflash {
+        code: PathBuf,
+        vars: PathBuf,
+    },
+}
+
+impl super::IntoArguments for Firmware {
+    fn into_arguments(self) -> Vec<String> {
+        match self {
+            Firmware::Bios(path) => vec![String::from("-bios"), path.display().to_string()],
+            Firmware::Pflash{code, vars} => {
+                let code = super::escape(&code.display().to_string());
+                let vars = super::escape(&vars.display().to_string());
+
+                vec![
+                    String::from("-drive"), format!("if=pflash,format=raw,unit=0,readonly=on,file={}", code),
+                    String::from("-drive"), format!("if=pflash,format=raw,unit=1,file={}", vars),
+                ]
+            },
+        }
+    }
+}
+
+/// Devices the firmware can boot from.
+pub enum BootDevice {
+    Floppy,
+    Disk,
+    Cdrom,
+    Network,
+}
+
+/// Represents the boot order and boot menu settings.
+pub struct Boot {
+    order: Vec<BootDevice>,
+    menu: Option<bool>,
+    reboot_timeout: Option<Duration>,
+}
+
+impl Boot {
+    /// Try to boot from the specified devices, in order. Returns an error if no device is
+    /// specified.
+    pub fn new(order: Vec<BootDevice>) -> Result<Boot> {
+        if order.is_empty() {
+            return Err(InitError::InvalidConfig{msg: String::from("at least one boot device must be defined")}.into());
+        }
+
+        Ok(Boot {
+            order,
+            menu: None,
+            reboot_timeout: None,
+        })
+    }
+
+    /// Specify wether the firmware should show an interactive boot menu.
+    pub fn set_menu(mut self, menu: bool) -> Self {
+        self.menu = Some(menu);
+        self
+    }
+
+    /// Reboot after the specified delay when no bootable device is found, instead of halting.
+    pub fn set_reboot_timeout(mut self, timeout: Duration) -> Self {
+        self.reboot_timeout = Some(timeout);
+        self
+    }
+}
+
+impl super::IntoArguments for Boot {
+    fn into_arguments(self) -> Vec<String> {
+        let order = self.order.into_iter()
+            .map(|device| match device {
+                BootDevice::Floppy => 'a',
+                BootDevice::Disk => 'c',
+                BootDevice::Cdrom => 'd',
+                BootDevice::Network => 'n',
+            })
+            .collect::<String>();
+
+        let mut settings = format!("order={}", order);
+
+        if let Some(menu) = self.menu {
+            settings.push_str(if menu { ",menu=on" } else { ",menu=off" });
+        }
+
+        if let Some(timeout) = self.reboot_timeout {
+            settings.push_str(&format!(",reboot-timeout={}", timeout.as_millis()));
+        }
+
+        vec![String::from("-boot"), settings]
+    }
+}
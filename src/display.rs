@@ -1,11 +1,16 @@
 //! QEMU Display settings.
 
+use std::path::PathBuf;
+
 /// VNC Display settings.
 pub struct Vnc {
     host: String,
     display: u16,
     ws_port: Option<u16>,
     password: bool,
+    x509_dir: Option<PathBuf>,
+    x509_verify: bool,
+    legacy_tls: bool,
 }
 
 impl Vnc {
@@ -13,9 +18,12 @@ impl Vnc {
     pub fn new<S: Into<String>>(host: S, display: u16) -> Vnc {
         Vnc {
             host: host.into(),
-            display: display,
+            display,
             ws_port: None,
             password: false,
+            x509_dir: None,
+            x509_verify: false,
+            legacy_tls: false,
         }
     }
 
@@ -23,17 +31,128 @@ impl Vnc {
     pub fn with_websocket<S: Into<String>>(host: S, display: u16, ws_port: u16) -> Vnc {
         Vnc {
             host: host.into(),
-            display: display,
+            display,
             ws_port: Some(ws_port),
             password: false,
+            x509_dir: None,
+            x509_verify: false,
+            legacy_tls: false,
         }
     }
 
     /// Specify wether the VNC server should require a password. The password must be set separetly
-    /// using the QEMU Monitor.
+    /// using the QEMU Monitor, see `monitor::MonitorClient`.
     pub fn use_password(&mut self, passwd: bool) {
         self.password = passwd;
     }
+
+    /// Encrypt VNC connections with TLS, using the x509 certificates found in `x509_dir`
+    /// (`ca-cert.pem`, `server-cert.pem` and `server-key.pem`). When `verify` is set, clients
+    /// must also present a certificate signed by the CA.
+    pub fn use_tls<P: Into<PathBuf>>(&mut self, x509_dir: P, verify: bool) {
+        self.x509_dir = Some(x509_dir.into());
+        self.x509_verify = verify;
+    }
+
+    /// Specify wether TLS should be configured with the legacy `tls,x509=` VNC options rather than
+    /// with a `tls-creds-x509` object, for QEMU versions older than 2.5.
+    pub fn use_legacy_tls(&mut self, legacy: bool) {
+        self.legacy_tls = legacy;
+    }
+}
+
+/// SPICE Display settings.
+pub struct Spice {
+    port: u16,
+    tls_port: Option<u16>,
+    disable_ticketing: bool,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    agent: bool,
+}
+
+impl Spice {
+    /// Create a new SPICE display configuration listening on the specified port.
+    pub fn new(port: u16) -> Spice {
+        Spice {
+            port,
+            tls_port: None,
+            disable_ticketing: false,
+            password: None,
+            password_file: None,
+            agent: false,
+        }
+    }
+
+    /// Create a new SPICE display configuration with an additional TLS port.
+    pub fn with_tls(port: u16, tls_port: u16) -> Spice {
+        Spice {
+            tls_port: Some(tls_port),
+            ..Spice::new(port)
+        }
+    }
+
+    /// Allow clients to connect without authentication.
+    pub fn disable_ticketing(&mut self) {
+        self.disable_ticketing = true;
+    }
+
+    /// Require clients to authenticate with the specified password. The password is passed on
+    /// QEMU's command line, where any local user can read it: prefer `set_password_file`.
+    pub fn set_password<S: Into<String>>(&mut self, passwd: S) {
+        self.password = Some(passwd.into());
+    }
+
+    /// Require clients to authenticate with the password stored in the specified file, which
+    /// must exist and only be readable by its owner when QEMU starts. Requires QEMU 7.0 or newer.
+    pub fn set_password_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.password_file = Some(path.into());
+    }
+
+    /// Specify wether to add the virtio-serial channel used by the SPICE guest agent, which
+    /// enables features such as clipboard sharing and automatic resolution.
+    pub fn use_agent(&mut self, agent: bool) {
+        self.agent = agent;
+    }
+
+    /// Build the `-spice` option, and the devices of the guest agent channel if enabled.
+    fn arguments(self) -> Vec<String> {
+        let mut param = format!("port={}", self.port);
+
+        if let Some(tls_port) = self.tls_port {
+            param.push_str(&format!(",tls-port={}", tls_port));
+        }
+
+        if self.disable_ticketing {
+            param.push_str(",disable-ticketing=on");
+        }
+
+        if let Some(password) = self.password {
+            param.push_str(&format!(",password={}", super::escape(&password)));
+        }
+
+        let mut args = Vec::new();
+
+        // The secret object must be defined before the option referencing it.
+        if let Some(path) = self.password_file {
+            args.push(String::from("-object"));
+            args.push(format!("secret,id=spice-pw0,file={}", super::escape(&path.display().to_string())));
+            param.push_str(",password-secret=spice-pw0");
+        }
+
+        args.push(String::from("-spice"));
+        args.push(param);
+
+        if self.agent {
+            args.extend(vec![
+                String::from("-device"), String::from("virtio-serial"),
+                String::from("-chardev"), String::from("spicevmc,id=vdagent,name=vdagent"),
+                String::from("-device"), String::from("virtserialport,chardev=vdagent,name=com.redhat.spice.0"),
+            ]);
+        }
+
+        args
+    }
 }
 
 /// Represnts the settings of a display used with a machine.
@@ -41,6 +160,12 @@ pub enum Display {
     None,
     Sdl,
     Vnc(Vnc),
+    Spice(Spice),
+
+    /// Disable graphical output entirely, and multiplex the guest's serial port and the QEMU
+    /// monitor on standard input/output. This conflicts with redirecting the serial port or the
+    /// monitor to stdio as well, which `Builder::start` rejects.
+    NoGraphic,
 }
 
 impl super::IntoArguments for Display {
@@ -57,12 +182,31 @@ impl super::IntoArguments for Display {
                     param.push_str(&format!(",websocket={}", ws_port));
                 }
 
-                if vnc.password == true {
+                if vnc.password {
                     param.push_str(&String::from(",password"));
                 }
 
+                if let Some(dir) = vnc.x509_dir {
+                    let dir = super::escape(&dir.display().to_string());
+
+                    if vnc.legacy_tls {
+                        let opt = if vnc.x509_verify { "x509verify" } else { "x509" };
+                        param.push_str(&format!(",tls,{}={}", opt, dir));
+                    } else {
+                        let creds = format!("tls-creds-x509,id=vnc-tls0,dir={},endpoint=server,verify-peer={}",
+                            dir, if vnc.x509_verify { "on" } else { "off" });
+
+                        param.push_str(",tls-creds=vnc-tls0");
+                        return vec![String::from("-object"), creds, String::from("-display"), param];
+                    }
+                }
+
                 param
             },
+
+            // SPICE is configured with its own option rather than as a `-display` type.
+            Display::Spice(spice) => return spice.arguments(),
+            Display::NoGraphic => return vec![String::from("-nographic")],
         };
 
         args.push(param);
@@ -0,0 +1,115 @@
+//! QEMU drive options.
+
+use image::Format;
+
+use std::path::PathBuf;
+
+/// Type of interface the drive is connected on.
+pub enum Interface {
+    Virtio,
+    Ide,
+    Scsi,
+}
+
+/// Host cache mode used when accessing the drive's image.
+pub enum Cache {
+    None,
+    Writeback,
+    Writethrough,
+    DirectSync,
+    Unsafe,
+}
+
+/// Represents a disk drive attached to the machine.
+pub struct Drive {
+    file: PathBuf,
+    format: Option<Format>,
+    interface: Option<Interface>,
+    cache: Option<Cache>,
+    read_only: bool,
+    snapshot: bool,
+}
+
+impl Drive {
+    /// Create a new drive backed by the specified image file. QEMU's defaults will be used for
+    /// every other setting.
+    pub fn new<P: Into<PathBuf>>(file: P) -> Drive {
+        Drive {
+            file: file.into(),
+            format: None,
+            interface: None,
+            cache: None,
+            read_only: false,
+            snapshot: false,
+        }
+    }
+
+    /// Set the format of the image file, instead of letting QEMU probe it.
+    pub fn set_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Set the interface the drive is connected on.
+    pub fn set_interface(mut self, interface: Interface) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Set the host cache mode of the drive.
+    pub fn set_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Specify wether the guest should only be allowed to read from the drive.
+    pub fn set_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Specify wether writes to the drive should be discarded when QEMU exits, leaving its image
+    /// untouched. This is the per-drive equivalent of `Builder::snapshot`.
+    pub fn set_snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+}
+
+impl super::IntoArguments for Drive {
+    fn into_arguments(self) -> Vec<String> {
+        let mut opts = vec![format!("file={}", super::escape(&self.file.display().to_string()))];
+
+        if let Some(format) = self.format {
+            opts.push(format!("format={}", format));
+        }
+
+        if let Some(interface) = self.interface {
+            opts.push(format!("if={}", match interface {
+                Interface::Virtio => "virtio",
+                Interface::Ide => "ide",
+                Interface::Scsi => "scsi",
+            }));
+        }
+
+        if let Some(cache) = self.cache {
+            opts.push(format!("cache={}", match cache {
+                Cache::None => "none",
+                Cache::Writeback => "writeback",
+                Cache::Writethrough => "writethrough",
+                Cache::DirectSync => "directsync",
+                Cache::Unsafe => "unsafe",
+            }));
+        }
+
+        if self.read_only {
+            opts.push(String::from("readonly=on"));
+        }
+
+        if self.snapshot {
+            opts.push(String::from("snapshot=on"));
+        }
+
+        vec![String::from("-drive"), opts.join(",")]
+    }
+}
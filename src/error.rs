@@ -1,7 +1,12 @@
 //! Error handling functionality.
 
+// The `Fail` derive expands to impl blocks nested in anonymous constants.
+#![allow(non_local_definitions)]
+
 use failure;
 
+use std::time::Duration;
+
 pub type Result<T> = ::std::result::Result<T, failure::Error>;
 
 #[derive(Debug, Fail)]
@@ -15,4 +20,64 @@ pub enum InitError {
     InvalidConfig {
         msg: String,
     },
+
+    #[fail(display = "QEMU exited during startup with code {:?}: {}", code, stderr)]
+    QemuExited {
+        code: Option<i32>,
+        stderr: String,
+    },
+
+    #[fail(display = "QEMU was not ready after {:?}", timeout)]
+    NotReady {
+        timeout: Duration,
+    },
+
+    #[fail(display = "could not find the QEMU version in: {}", output)]
+    UnknownVersion {
+        output: String,
+    },
+}
+
+#[derive(Debug, Fail)]
+pub enum QmpError {
+    #[fail(display = "QMP command failed: {}: {}", class, desc)]
+    Command {
+        class: String,
+        desc: String,
+    },
+
+    #[fail(display = "QMP protocol error: {}", msg)]
+    Protocol {
+        msg: String,
+    },
+}
+
+#[derive(Debug, Fail)]
+pub enum MonitorError {
+    #[fail(display = "monitor connection closed by QEMU")]
+    ConnectionClosed,
+}
+
+#[derive(Debug, Fail)]
+pub enum ImageError {
+    #[fail(display = "qemu-img failed with code {:?}: {}", code, stderr)]
+    QemuImg {
+        code: Option<i32>,
+        stderr: String,
+    },
+
+    #[fail(display = "invalid qemu-img info output: {}", msg)]
+    InvalidInfo {
+        msg: String,
+    },
+
+    #[fail(display = "unsupported image operation: {}", msg)]
+    Unsupported {
+        msg: String,
+    },
+
+    #[fail(display = "invalid image size: {}", size)]
+    InvalidSize {
+        size: String,
+    },
 }
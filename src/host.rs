@@ -0,0 +1,95 @@
+//! Information about the QEMU installation of the host.
+
+use error::{InitError, Result};
+
+use std::fmt;
+use std::io;
+use std::process::Command;
+
+/// Version of a QEMU executable. Versions can be compared to check for the availability of a
+/// feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Create a new version number.
+    pub fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Return the version of the specified QEMU executable, as reported by `--version`.
+pub fn version(executable: &str) -> Result<Version> {
+    let output = run(executable, &["--version"])?;
+
+    parse_version(&output).ok_or_else(|| InitError::UnknownVersion{output: output.trim().to_string()}.into())
+}
+
+/// Return the names of the machine types supported by the specified QEMU executable, as listed by
+/// `-machine help`. Aliases such as `pc` or `q35` are included.
+pub fn machine_types(executable: &str) -> Result<Vec<String>> {
+    let output = run(executable, &["-machine", "help"])?;
+
+    // Skip the "Supported machines are:" title.
+    let types = output.lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect();
+
+    Ok(types)
+}
+
+/// Parse the `QEMU emulator version X.Y.Z` line printed by `--version`. The patch number is
+/// optional, and anything following the version, such as a distribution suffix, is ignored.
+fn parse_version(output: &str) -> Option<Version> {
+    let line = output.lines().find(|line| line.starts_with("QEMU emulator version "))?;
+    let number = line["QEMU emulator version ".len()..]
+        .split(|c: char| c != '.' && !c.is_ascii_digit())
+        .next()?;
+
+    let mut parts = number.split('.').map(|part| part.parse::<u32>().ok());
+
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = match parts.next() {
+        Some(patch) => patch?,
+        None => 0,
+    };
+
+    Some(Version::new(major, minor, patch))
+}
+
+/// Run QEMU with the specified arguments, and return what it printed on its standard output.
+fn run(executable: &str, args: &[&str]) -> Result<String> {
+    let output = match Command::new(executable).args(args).output() {
+        Ok(output) => output,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(InitError::ExecutableNotFound{exec: executable.to_string()}.into());
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    if !output.status.success() {
+        return Err(InitError::QemuExited {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }.into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,449 @@
+//! QEMU disk images.
+
+use error::{ImageError, Result};
+use serde_json::{self, Value};
+
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::unix::fs::OpenOptionsExt;
+
+/// Disk image formats supported by QEMU.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Raw,
+    Qcow2,
+    Qcow,
+    Vmdk,
+    Vdi,
+    Vhdx,
+    Luks,
+    Unknown(String),
+}
+
+impl<'a> From<&'a str> for Format {
+    fn from(name: &'a str) -> Format {
+        match name {
+            "raw" => Format::Raw,
+            "qcow2" => Format::Qcow2,
+            "qcow" => Format::Qcow,
+            "vmdk" => Format::Vmdk,
+            "vdi" => Format::Vdi,
+            "vhdx" => Format::Vhdx,
+            "luks" => Format::Luks,
+            name => Format::Unknown(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Format::Raw => "raw",
+            Format::Qcow2 => "qcow2",
+            Format::Qcow => "qcow",
+            Format::Vmdk => "vmdk",
+            Format::Vdi => "vdi",
+            Format::Vhdx => "vhdx",
+            Format::Luks => "luks",
+            Format::Unknown(ref name) => name,
+        })
+    }
+}
+
+/// Internal snapshot of a qcow2 image, as reported by `qemu-img info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub id: String,
+    pub tag: String,
+    /// Size of the saved VM state in bytes, zero for disk-only snapshots.
+    pub vm_size: u64,
+    /// Creation time of the snapshot, in seconds since the Unix epoch.
+    pub date: u64,
+}
+
+/// Result of an image consistency check, as reported by `qemu-img check`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckReport {
+    pub corruptions: u64,
+    pub leaks: u64,
+    pub errors: u64,
+    pub image_end_offset: Option<u64>,
+}
+
+/// Represents a disk image file, managed using `qemu-img`.
+pub struct Image {
+    path: PathBuf,
+    format: Format,
+    size: u64,
+    actual_size: Option<u64>,
+    backing_file: Option<PathBuf>,
+    backing_format: Option<Format>,
+    encrypted: bool,
+}
+
+impl Image {
+    /// Describe an image at the specified path, with the specified format and virtual size in
+    /// bytes. The file itself is not created, see `create`.
+    pub fn new<P: Into<PathBuf>>(path: P, format: Format, size: u64) -> Image {
+        Image {
+            path: path.into(),
+            format,
+            size,
+            actual_size: None,
+            backing_file: None,
+            backing_format: None,
+            encrypted: false,
+        }
+    }
+
+    /// Describe a copy-on-write overlay at the specified path, on top of the `backing` image.
+    /// The overlay has the same virtual size as its backing image. Only the qcow2 and qcow formats
+    /// support backing files.
+    pub fn with_backing<P: Into<PathBuf>>(path: P, format: Format, backing: &Image) -> Result<Image> {
+        match format {
+            Format::Qcow2 | Format::Qcow => {},
+            _ => return Err(ImageError::Unsupported{msg: format!("{} images can't have a backing file", format)}.into()),
+        }
+
+        Ok(Image {
+            path: path.into(),
+            format,
+            size: backing.size,
+            actual_size: None,
+            backing_file: Some(backing.path.clone()),
+            backing_format: Some(backing.format.clone()),
+            encrypted: false,
+        })
+    }
+
+    /// Inspect the existing image at the specified path.
+    pub fn info<P: Into<PathBuf>>(path: P) -> Result<Image> {
+        let path = path.into();
+
+        let output = qemu_img(qemu_img_command()
+            .arg("info")
+            .arg("--output=json")
+            .arg(&path))?;
+
+        let info: Value = serde_json::from_str(&output)?;
+
+        let format = match info["format"].as_str() {
+            Some(format) => Format::from(format),
+            None => return Err(ImageError::InvalidInfo{msg: String::from("missing format")}.into()),
+        };
+
+        let size = match info["virtual-size"].as_u64() {
+            Some(size) => size,
+            None => return Err(ImageError::InvalidInfo{msg: String::from("missing virtual size")}.into()),
+        };
+
+        Ok(Image {
+            path,
+            format,
+            size,
+            actual_size: info["actual-size"].as_u64(),
+            backing_file: info["backing-filename"].as_str().map(PathBuf::from),
+            backing_format: info["backing-filename-format"].as_str().map(Format::from),
+            encrypted: info["encrypted"].as_bool().unwrap_or(false),
+        })
+    }
+
+    /// Check the consistency of the existing image at the specified path. Corruptions and leaked
+    /// clusters are reported through the returned `CheckReport` rather than as an error.
+    pub fn check<P: AsRef<Path>>(path: P) -> Result<CheckReport> {
+        let output = qemu_img_command()
+            .arg("check")
+            .arg("--output=json")
+            .arg(path.as_ref())
+            .output()?;
+
+        // Exit codes 2 and 3 mean corruptions or leaks were found, as detailed by the report.
+        match output.status.code() {
+            Some(0) | Some(2) | Some(3) => {},
+            code => {
+                return Err(ImageError::QemuImg {
+                    code,
+                    stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                }.into());
+            },
+        }
+
+        let report: Value = serde_json::from_slice(&output.stdout)?;
+
+        // Counters are omitted from the report when they are zero.
+        Ok(CheckReport {
+            corruptions: report["corruptions"].as_u64().unwrap_or(0),
+            leaks: report["leaks"].as_u64().unwrap_or(0),
+            errors: report["check-errors"].as_u64().unwrap_or(0),
+            image_end_offset: report["image-end-offset"].as_u64(),
+        })
+    }
+
+    /// Return the path of the image file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the format of the image.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// Return the virtual size of the image in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Return the space used by the image file on the host in bytes, if known. This is only
+    /// available for images returned by `info`.
+    pub fn actual_size(&self) -> Option<u64> {
+        self.actual_size
+    }
+
+    /// Return the path of the backing file of the image, if it has one.
+    pub fn backing_file(&self) -> Option<&Path> {
+        self.backing_file.as_deref()
+    }
+
+    /// Return the format of the backing file of the image, if known.
+    pub fn backing_format(&self) -> Option<&Format> {
+        self.backing_format.as_ref()
+    }
+
+    /// Return wether the image data is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Create the image file on disk.
+    pub fn create(&self) -> Result<()> {
+        let mut command = qemu_img_command();
+        command.arg("create").arg("-f").arg(self.format.to_string());
+
+        // The size of an overlay is taken from its backing file.
+        match self.backing_file {
+            Some(ref backing_file) => {
+                command.arg("-b").arg(backing_file);
+
+                if let Some(ref backing_format) = self.backing_format {
+                    command.arg("-F").arg(backing_format.to_string());
+                }
+
+                command.arg(&self.path);
+            },
+            None => {
+                command.arg(&self.path).arg(self.size.to_string());
+            },
+        }
+
+        qemu_img(&mut command)?;
+        Ok(())
+    }
+
+    /// Convert the image to another format, writing the result at `dest_path`. When `compress` is
+    /// set, the data of the destination image is compressed, which requires a format supporting
+    /// it such as qcow2.
+    pub fn convert<P: Into<PathBuf>>(&self, dest_path: P, dest_format: Format, compress: bool) -> Result<Image> {
+        let dest = Image::new(dest_path, dest_format, self.size);
+
+        let mut command = qemu_img_command();
+        command.arg("convert")
+            .arg("-f").arg(self.format.to_string())
+            .arg("-O").arg(dest.format.to_string());
+
+        if compress {
+            command.arg("-c");
+        }
+
+        qemu_img(command.arg(&self.path).arg(&dest.path))?;
+        Ok(dest)
+    }
+
+    /// Create the image file on disk, encrypted with LUKS using the specified passphrase. Only the
+    /// qcow2 and luks formats support encryption. The passphrase is handed to `qemu-img` through
+    /// a temporary file only readable by the current user, so that it never appears on its
+    /// command line.
+    pub fn create_encrypted(&mut self, passphrase: &str) -> Result<()> {
+        let encrypt_opts = match self.format {
+            Format::Qcow2 => "encrypt.format=luks,encrypt.key-secret=sec0",
+            Format::Luks => "key-secret=sec0",
+            _ => return Err(ImageError::Unsupported{msg: format!("{} images can't be encrypted", self.format)}.into()),
+        };
+
+        let secret = env::temp_dir().join(format!("qemu-rs-secret-{}-{}", process::id(), SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos()));
+
+        let written = OpenOptions::new().write(true).create_new(true).mode(0o600)
+            .open(&secret)?
+            .write_all(passphrase.as_bytes());
+
+        // The secret file must not be left behind, even partially written.
+        let result = written.map_err(Into::into).and_then(|()| qemu_img(qemu_img_command()
+            .arg("create")
+            .arg("--object").arg(format!("secret,id=sec0,file={}", super::escape(&secret.display().to_string())))
+            .arg("-f").arg(self.format.to_string())
+            .arg("-o").arg(encrypt_opts)
+            .arg(&self.path)
+            .arg(self.size.to_string())));
+
+        fs::remove_file(&secret)?;
+        result?;
+
+        self.encrypted = true;
+        Ok(())
+    }
+
+    /// Take an internal snapshot of the image, named `name`. Only qcow2 images support internal
+    /// snapshots.
+    pub fn create_snapshot(&self, name: &str) -> Result<()> {
+        self.qemu_img_snapshot("-c", Some(name))?;
+        Ok(())
+    }
+
+    /// List the internal snapshots of the image.
+    pub fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        self.check_snapshots_supported()?;
+
+        let output = qemu_img(qemu_img_command()
+            .arg("info")
+            .arg("--output=json")
+            .arg("-f").arg(self.format.to_string())
+            .arg(&self.path))?;
+
+        parse_snapshots(&serde_json::from_str(&output)?)
+    }
+
+    /// Revert the image to the internal snapshot named `name`.
+    pub fn apply_snapshot(&self, name: &str) -> Result<()> {
+        self.qemu_img_snapshot("-a", Some(name))?;
+        Ok(())
+    }
+
+    /// Delete the internal snapshot named `name`.
+    pub fn delete_snapshot(&self, name: &str) -> Result<()> {
+        self.qemu_img_snapshot("-d", Some(name))?;
+        Ok(())
+    }
+
+    /// Return an error if the format of the image doesn't support internal snapshots.
+    fn check_snapshots_supported(&self) -> Result<()> {
+        if self.format != Format::Qcow2 {
+            let msg = format!("{} images don't support internal snapshots", self.format);
+            return Err(ImageError::Unsupported{msg}.into());
+        }
+
+        Ok(())
+    }
+
+    /// Run `qemu-img snapshot` with the specified operation flag.
+    fn qemu_img_snapshot(&self, op: &str, name: Option<&str>) -> Result<String> {
+        self.check_snapshots_supported()?;
+
+        let mut command = qemu_img_command();
+        command.arg("snapshot").arg("-f").arg(self.format.to_string()).arg(op);
+
+        if let Some(name) = name {
+            command.arg(name);
+        }
+
+        qemu_img(command.arg(&self.path))
+    }
+
+    /// Change the virtual size of the image to `new_size` bytes.
+    pub fn resize(&mut self, new_size: u64) -> Result<()> {
+        self.qemu_img_resize(new_size.to_string(), new_size < self.size)?;
+        self.size = new_size;
+
+        Ok(())
+    }
+
+    /// Grow the virtual size of the image by `delta` bytes, or shrink it if `delta` is negative.
+    pub fn resize_by(&mut self, delta: i64) -> Result<()> {
+        let new_size = if delta < 0 {
+            self.size.checked_sub(delta.unsigned_abs())
+        } else {
+            self.size.checked_add(delta as u64)
+        };
+
+        let new_size = match new_size {
+            Some(size) => size,
+            None => return Err(ImageError::InvalidSize{size: format!("{:+}", delta)}.into()),
+        };
+
+        self.qemu_img_resize(format!("{:+}", delta), delta < 0)?;
+        self.size = new_size;
+
+        Ok(())
+    }
+
+    /// Run `qemu-img resize` with the specified size argument.
+    fn qemu_img_resize(&self, size: String, shrink: bool) -> Result<()> {
+        let mut command = qemu_img_command();
+        command.arg("resize").arg("-f").arg(self.format.to_string());
+
+        // Shrinking an image discards data, so qemu-img wants it to be explicitly requested.
+        if shrink {
+            command.arg("--shrink");
+        }
+
+        qemu_img(command.arg(&self.path).arg("--").arg(size))?;
+        Ok(())
+    }
+}
+
+/// Parse the `snapshots` array of the JSON printed by `qemu-img info`, which is omitted when the
+/// image has no internal snapshots.
+pub(crate) fn parse_snapshots(info: &Value) -> Result<Vec<Snapshot>> {
+    let snapshots = match info.get("snapshots") {
+        Some(snapshots) => snapshots,
+        None => return Ok(Vec::new()),
+    };
+
+    let snapshots = match snapshots.as_array() {
+        Some(snapshots) => snapshots,
+        None => return Err(ImageError::InvalidInfo{msg: String::from("snapshots is not an array")}.into()),
+    };
+
+    snapshots.iter().map(|snapshot| {
+        let fields = (snapshot["id"].as_str(), snapshot["name"].as_str(),
+            snapshot["vm-state-size"].as_u64(), snapshot["date-sec"].as_u64());
+
+        match fields {
+            (Some(id), Some(tag), Some(vm_size), Some(date)) => Ok(Snapshot {
+                id: id.to_string(),
+                tag: tag.to_string(),
+                vm_size,
+                date,
+            }),
+
+            _ => Err(ImageError::InvalidInfo{msg: format!("invalid snapshot: {}", snapshot)}.into()),
+        }
+    }).collect()
+}
+
+/// Create the command running `qemu-img`, which is looked up in the system's PATH.
+fn qemu_img_command() -> Command {
+    Command::new("qemu-img")
+}
+
+/// Run the specified `qemu-img` command, and return its standard output. A failure is reported
+/// along with the error message printed by `qemu-img`.
+fn qemu_img(command: &mut Command) -> Result<String> {
+    let output = command.output()?;
+
+    if !output.status.success() {
+        return Err(ImageError::QemuImg {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }.into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
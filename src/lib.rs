@@ -1,24 +1,56 @@
 //! # qemu-rs
 //! QEMU as a Rust library.
+//!
+//! Only Unix hosts are supported: instances are controlled through Unix sockets and signals.
 
 #[macro_use]
 extern crate failure;
+extern crate libc;
+#[macro_use]
+extern crate serde_json;
 
 pub mod error;
 pub mod machine;
+pub mod audio;
 pub mod display;
+pub mod boot;
+pub mod drive;
+pub mod host;
+pub mod image;
+pub mod network;
+pub mod qmp;
+pub mod monitor;
+pub mod serial;
+pub mod share;
+
+mod watchdog;
 
+use std::cmp;
 use std::env;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::sync::mpsc::{self, Sender};
+use std::path::{Path, PathBuf};
 use std::ffi::OsString;
-use std::process::{Command, Child};
+use std::time::{Duration, Instant};
+use std::process::{Command, Child, ChildStdout, ChildStderr, ExitStatus, Stdio};
 
 use error::Result;
+use watchdog::Watchdog;
 
 /// Object used to initialize a new QEMU instance with the specified parameters.
 pub struct Builder {
     executable: OsString,
     params: Vec<String>,
+    debug_threads: bool,
+    no_user_config: bool,
+    exit_on_guest_exit: bool,
+    run_timeout: Option<Duration>,
+    startup_check: Option<StartupCheck>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+    snapshot: bool,
+    qmp: Option<PathBuf>,
 }
 
 impl Builder {
@@ -45,7 +77,7 @@ impl Builder {
                 if let Some(path) = path {
                     path.into_os_string()
                 } else {
-                    return Err(error::InitError::ExecutableNotFound{exec: exec}.into());
+                    return Err(error::InitError::ExecutableNotFound{exec}.into());
                 }
             },
 
@@ -55,33 +87,523 @@ impl Builder {
         Ok(Builder {
             executable: exec_path,
             params: Vec::new(),
+            debug_threads: false,
+            no_user_config: false,
+            exit_on_guest_exit: false,
+            run_timeout: None,
+            startup_check: None,
+            capture_stdout: false,
+            capture_stderr: false,
+            snapshot: false,
+            qmp: None,
         })
     }
 
+    /// Create a new Builder using the QEMU executable emulating the specified architecture, as
+    /// found in the system's PATH.
+    pub fn for_arch(arch: machine::Arch) -> Result<Builder> {
+        Builder::new(arch.executable())
+    }
+
     /// Use the behavior defined in the specified object, and pass it as QEMU emulator options.
     pub fn set<A: IntoArguments>(mut self, a: A) -> Self {
         self.params.extend(a.into_arguments());
         self
     }
 
-    /// Start the QEMU emulator. Immediatly returns the control to the control to the caller, does
-    /// not wait on the spawned child process.
+    /// Enable semihosting for bare-metal guests, using the specified configuration.
+    pub fn semihosting(self, config: machine::SemihostingConfig) -> Self {
+        self.set(config)
+    }
+
+    /// Make QEMU listen for QMP clients on the specified Unix socket. See `qmp::QmpClient`.
+    pub fn qmp<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.params.push(String::from("-qmp"));
+        self.params.push(format!("unix:{},server,nowait", escape(&path.as_ref().display().to_string())));
+        self.qmp = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Specify wether QEMU should give its threads a name (`CPU 0/KVM`, ...), making them visible
+    /// in tools such as `top -H` or `perf`.
+    pub fn debug_threads(mut self, enable: bool) -> Self {
+        self.debug_threads = enable;
+        self
+    }
+
+    /// Prevent QEMU from loading the system and user configuration files, so that only the
+    /// options explicitly set on this builder apply. Configuration files passed with
+    /// `-readconfig` are still read.
+    pub fn no_user_config(mut self) -> Self {
+        self.no_user_config = true;
+        self
+    }
+
+    /// Discard every write made to the writable drives of the machine when QEMU exits, using
+    /// temporary overlays instead of modifying the images. To only protect some of the drives, see
+    /// `drive::Drive::set_snapshot` instead.
+    pub fn snapshot(mut self) -> Self {
+        self.snapshot = true;
+        self
+    }
+
+    /// Make QEMU terminate when the guest reboots instead of restarting it, so that a guest
+    /// which resets once it is done also ends the instance. Combined with semihosting, this lets
+    /// `Instance::wait` report the guest's outcome whichever way it stops.
+    pub fn exit_on_guest_exit(mut self) -> Self {
+        self.exit_on_guest_exit = true;
+        self
+    }
+
+    /// Kill the QEMU process if it is still running after the specified duration. When that
+    /// happens, `Instance::wait` returns a `RunStatus::TimedOut` status.
+    pub fn run_timeout(mut self, limit: Duration) -> Self {
+        self.run_timeout = Some(limit);
+        self
+    }
+
+    /// Make `start` wait until QEMU is ready according to the specified check, so that an invalid
+    /// configuration is reported as an `InitError::QemuExited` error rather than a seemingly
+    /// running instance. The process is polled, so `start` returns as soon as it exits.
+    pub fn startup_check(mut self, check: StartupCheck) -> Self {
+        self.startup_check = Some(check);
+        self
+    }
+
+    /// Pipe the standard output of QEMU, instead of inheriting it from the current process. The
+    /// output can then be read using `Instance::take_stdout`.
+    pub fn capture_stdout(mut self) -> Self {
+        self.capture_stdout = true;
+        self
+    }
+
+    /// Pipe the standard error of QEMU, instead of inheriting it from the current process. The
+    /// diagnostics printed by QEMU can then be read using `Instance::take_stderr` or
+    /// `Instance::read_stderr_to_string`.
+    pub fn capture_stderr(mut self) -> Self {
+        self.capture_stderr = true;
+        self
+    }
+
+    /// Return the command line that `start` would run: the QEMU executable followed by all of its
+    /// arguments, in order. Nothing is spawned.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![self.executable.to_string_lossy().into_owned()];
+        args.extend(self.arguments());
+
+        args
+    }
+
+    /// Start the QEMU emulator. Unless a startup check is configured, immediatly returns the
+    /// control to the caller, does not wait on the spawned child process.
     pub fn start(self) -> Result<Instance> {
-        let mut command = Command::new(self.executable);
+        let args = self.arguments();
+        check_arguments(&args)?;
+
+        let mut command = Command::new(&self.executable);
+        command.args(args);
+
+        let scan_stdout = matches!(self.startup_check, Some(StartupCheck::SerialContains(..)));
 
-        for param in self.params {
-            command.arg(param);
+        if self.capture_stdout || scan_stdout {
+            command.stdout(Stdio::piped());
+        } else {
+            command.stdout(Stdio::inherit());
         }
 
-        Ok(Instance {
-            process: command.spawn()?,
-        })
+        // Keep hold of QEMU's error messages to report them if it fails to start.
+        if self.capture_stderr || self.startup_check.is_some() {
+            command.stderr(Stdio::piped());
+        } else {
+            command.stderr(Stdio::inherit());
+        }
+
+        let process = command.spawn()?;
+        let watchdog = self.run_timeout.map(|limit| Watchdog::start(process.id(), limit));
+
+        let mut instance = Instance {
+            process,
+            watchdog,
+            timed_out: false,
+            qmp: self.qmp,
+        };
+
+        if let Some(check) = self.startup_check {
+            instance.check_startup(check, !self.capture_stdout, !self.capture_stderr)?;
+        }
+
+        Ok(instance)
+    }
+
+    /// Build the list of command line arguments that will be passed to QEMU.
+    fn arguments(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if self.no_user_config {
+            args.push(String::from("-no-user-config"));
+        }
+
+        if self.snapshot {
+            args.push(String::from("-snapshot"));
+        }
+
+        args.extend(self.params.iter().cloned());
+
+        if self.exit_on_guest_exit {
+            args.push(String::from("-no-reboot"));
+        }
+
+        if self.debug_threads {
+            // Extend an already specified name rather than passing `-name` twice.
+            match args.iter().position(|arg| arg == "-name") {
+                Some(i) if i + 1 < args.len() => args[i + 1].push_str(",debug-threads=on"),
+                _ => {
+                    args.push(String::from("-name"));
+                    args.push(String::from("debug-threads=on"));
+                },
+            }
+        }
+
+        args
+    }
+}
+
+/// Check the command line for options that QEMU would refuse to use together.
+fn check_arguments(args: &[String]) -> Result<()> {
+    if args.iter().any(|arg| arg == "-nographic") {
+        let stdio = args.windows(2)
+            .any(|opt| (opt[0] == "-serial" || opt[0] == "-monitor") && opt[1] == "stdio");
+
+        if stdio {
+            let msg = String::from("-nographic already uses stdio for the serial port and monitor");
+            return Err(error::InitError::InvalidConfig{msg}.into());
+        }
+    }
+
+    let spice_audio = args.windows(2)
+        .any(|opt| opt[0] == "-audiodev" && opt[1].starts_with("spice,"));
+
+    if spice_audio && !args.iter().any(|arg| arg == "-spice") {
+        let msg = String::from("the spice audio backend requires a SPICE display");
+        return Err(error::InitError::InvalidConfig{msg}.into());
+    }
+
+    Ok(())
+}
+
+/// Strategies used by `Builder::start` to decide that QEMU has started, each with the maximum time
+/// it may take.
+pub enum StartupCheck {
+    /// QEMU is considered started once it has been running for the specified grace period.
+    ProcessAlive(Duration),
+
+    /// QEMU is ready once its QMP server, configured with `Builder::qmp` on the specified socket,
+    /// answers a `query-status` command.
+    QmpReady(PathBuf, Duration),
+
+    /// QEMU is ready once the specified marker, such as a login prompt, is found on its standard
+    /// output, where a serial port configured with `serial::Serial::Stdio` is written. When the
+    /// output is captured, what was read until the marker was found isn't available anymore.
+    SerialContains(String, Duration),
+}
+
+/// How a QEMU process stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// QEMU exited on its own, or was killed by the caller.
+    Exited(ExitStatus),
+
+    /// QEMU was killed because it reached the limit set with `Builder::run_timeout`.
+    TimedOut(ExitStatus),
+}
+
+impl RunStatus {
+    /// Return the exit status of the QEMU process.
+    pub fn exit_status(&self) -> ExitStatus {
+        match *self {
+            RunStatus::Exited(status) | RunStatus::TimedOut(status) => status,
+        }
+    }
+
+    /// Return wether QEMU was killed because it reached its time limit.
+    pub fn timed_out(&self) -> bool {
+        match *self {
+            RunStatus::Exited(_) => false,
+            RunStatus::TimedOut(_) => true,
+        }
     }
 }
 
 /// Represents an running QEMU instance.
 pub struct Instance {
     process: Child,
+    watchdog: Option<Watchdog>,
+    timed_out: bool,
+    qmp: Option<PathBuf>,
+}
+
+impl Instance {
+    /// Wait for the QEMU process to exit, and return how it stopped. A process killed by its
+    /// time limit is reported as `RunStatus::TimedOut` by every call.
+    ///
+    /// When a semihosting guest calls `SYS_EXIT`, QEMU exits with the status reported by the
+    /// guest, which is then available through `RunStatus::exit_status`. Arbitrary exit codes
+    /// are propagated on AArch64, and on 32-bit Arm through `SYS_EXIT_EXTENDED` since QEMU 4.1;
+    /// RISC-V semihosting requires QEMU 7.0 or newer.
+    pub fn wait(&mut self) -> Result<RunStatus> {
+        if self.watchdog.is_some() {
+            watchdog::wait_exited(self.process.id())?;
+            self.stop_watchdog();
+        }
+
+        let status = self.process.wait()?;
+
+        Ok(self.run_status(status))
+    }
+
+    /// Check wether the QEMU process has exited without blocking, returning how it stopped if it
+    /// has.
+    pub fn try_wait(&mut self) -> Result<Option<RunStatus>> {
+        if self.watchdog.is_some() {
+            if watchdog::is_running(self.process.id()) {
+                return Ok(None);
+            }
+
+            self.stop_watchdog();
+        }
+
+        let status = self.process.try_wait()?;
+        Ok(status.map(|status| self.run_status(status)))
+    }
+
+    /// Forcibly kill the QEMU process.
+    pub fn kill(&mut self) -> Result<()> {
+        Ok(self.process.kill()?)
+    }
+
+    /// Ask the guest to shut down, and wait up to `timeout` for the QEMU process to exit before
+    /// killing it. Returns how the process stopped.
+    ///
+    /// If a QMP socket was configured, an ACPI power button press is sent to the guest through
+    /// it. Otherwise, or if QMP can't be reached within half of `timeout`, QEMU receives
+    /// `SIGTERM`, which makes it exit cleanly without involving the guest.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<RunStatus> {
+        let deadline = Instant::now() + timeout;
+
+        if let Some(status) = self.try_wait()? {
+            return Ok(status);
+        }
+
+        // The QMP server may be busy with another client, leave time for SIGTERM if it is.
+        let qmp_deadline = Instant::now() + timeout / 2;
+        let powerdown = self.qmp.as_ref().is_some_and(|path| qmp_execute(path, "system_powerdown", qmp_deadline));
+
+        if !powerdown {
+            unsafe {
+                libc::kill(self.process.id() as libc::pid_t, libc::SIGTERM);
+            }
+        }
+
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                // Best effort: the process may have exited in the meantime.
+                let _ = self.kill();
+                return self.wait();
+            }
+
+            thread::sleep(cmp::min(deadline - now, Duration::from_millis(10)));
+        }
+    }
+
+    /// Return the OS-assigned process identifier of the QEMU process.
+    pub fn pid(&self) -> u32 {
+        self.process.id()
+    }
+
+    /// Take the handle to the standard output of QEMU, if it was captured.
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.process.stdout.take()
+    }
+
+    /// Take the handle to the standard error of QEMU, if it was captured.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.process.stderr.take()
+    }
+
+    /// Read the captured standard error of QEMU until it is closed, which usually happens when
+    /// QEMU exits. Returns an empty string if the standard error was not captured, or was
+    /// already taken.
+    pub fn read_stderr_to_string(&mut self) -> Result<String> {
+        let mut stderr = String::new();
+
+        if let Some(mut pipe) = self.process.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+
+        Ok(stderr)
+    }
+
+    /// Wait until QEMU passes the specified startup check, returning an error along with what QEMU
+    /// printed on its piped standard error if it exits before that. Once started, the output
+    /// consumed by the check and the error messages are forwarded to the current process if
+    /// `forward_stdout` and `forward_stderr` are set.
+    fn check_startup(&mut self, check: StartupCheck, forward_stdout: bool, forward_stderr: bool) -> Result<()> {
+        match check {
+            StartupCheck::ProcessAlive(grace) => {
+                self.poll_startup(grace, || false)?;
+            },
+
+            StartupCheck::QmpReady(path, timeout) => {
+                let deadline = Instant::now() + timeout;
+
+                // QEMU may accept connections before being able to answer, don't wait past the
+                // deadline for it.
+                let ready = self.poll_startup(timeout, || qmp_execute(&path, "query-status", deadline))?;
+
+                if !ready {
+                    return self.not_ready(timeout);
+                }
+            },
+
+            StartupCheck::SerialContains(pattern, timeout) => {
+                let (tx, rx) = mpsc::channel();
+
+                if let Some(pipe) = self.process.stdout.take() {
+                    thread::spawn(move || scan_output(pipe, pattern.as_bytes(), forward_stdout, tx));
+                }
+
+                let mut found = None;
+                let ready = self.poll_startup(timeout, || {
+                    found = rx.try_recv().ok();
+                    found.is_some()
+                })?;
+
+                match found {
+                    Some(mut pipe) if ready => {
+                        // The rest of the output goes where it would have gone.
+                        if forward_stdout {
+                            thread::spawn(move || io::copy(&mut pipe, &mut io::stdout()));
+                        } else {
+                            self.process.stdout = Some(pipe);
+                        }
+                    },
+
+                    _ => return self.not_ready(timeout),
+                }
+            },
+        }
+
+        // QEMU started, from now on its error messages go where they would have gone.
+        if forward_stderr {
+            if let Some(mut pipe) = self.process.stderr.take() {
+                thread::spawn(move || io::copy(&mut pipe, &mut io::stderr()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Poll the QEMU process until `ready` returns true or `timeout` elapses, returning wether it
+    /// became ready. Returns an `InitError::QemuExited` error if QEMU exits in the meantime.
+    fn poll_startup<F: FnMut() -> bool>(&mut self, timeout: Duration, mut ready: F) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(status) = self.try_wait()? {
+                let stderr = self.read_stderr_to_string()?;
+
+                return Err(error::InitError::QemuExited {
+                    code: status.exit_status().code(),
+                    stderr: stderr.trim().to_string(),
+                }.into());
+            }
+
+            if ready() {
+                return Ok(true);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+
+            thread::sleep(cmp::min(deadline - now, Duration::from_millis(10)));
+        }
+    }
+
+    /// Kill QEMU, which failed to become ready within `timeout`, and report it.
+    fn not_ready(&mut self, timeout: Duration) -> Result<()> {
+        // Reap it through `wait` so that its watchdog is stopped too.
+        let _ = self.kill();
+        let _ = self.wait();
+
+        Err(error::InitError::NotReady{timeout}.into())
+    }
+
+    /// Must be called once the process has exited, but before it is reaped, to stop its watchdog
+    /// if any and remember wether it killed the process. The watchdog can't kill another process
+    /// reusing the pid this way.
+    fn stop_watchdog(&mut self) {
+        if let Some(watchdog) = self.watchdog.take() {
+            self.timed_out = watchdog.cancel();
+        }
+    }
+
+    /// Describe how the process stopped, given its exit status.
+    fn run_status(&self, status: ExitStatus) -> RunStatus {
+        if self.timed_out {
+            RunStatus::TimedOut(status)
+        } else {
+            RunStatus::Exited(status)
+        }
+    }
+}
+
+/// Execute a QMP command on the server listening on the specified socket, returning wether it
+/// succeeded before `deadline`.
+fn qmp_execute(path: &Path, command: &str, deadline: Instant) -> bool {
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+
+    qmp::QmpClient::connect_timeout(path, remaining())
+        .and_then(|mut client| {
+            client.set_timeout(Some(remaining()));
+            client.execute(command, None)
+        })
+        .is_ok()
+}
+
+/// Read QEMU's standard output until `pattern` is found, then hand the pipe back through `found`.
+/// What is read is also written to the standard output of the current process if `forward` is set.
+fn scan_output(mut pipe: ChildStdout, pattern: &[u8], forward: bool, found: Sender<ChildStdout>) {
+    let mut window = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        let n = match pipe.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+
+        if forward {
+            let _ = io::stdout().write_all(&chunk[..n]);
+        }
+
+        // Keep the end of the previous chunks, the pattern may span several of them.
+        window.extend_from_slice(&chunk[..n]);
+
+        if pattern.is_empty() || window.windows(pattern.len()).any(|w| w == pattern) {
+            let _ = found.send(pipe);
+            return;
+        }
+
+        let keep = cmp::min(window.len(), pattern.len() - 1);
+        window.drain(..window.len() - keep);
+    }
 }
 
 /// Trait implemented for every object that represent some kind of option of the QEMU emulator.
@@ -90,5 +612,22 @@ pub trait IntoArguments {
     fn into_arguments(self) -> Vec<String>;
 }
 
+/// Escape a value so it can be embedded in a comma-separated QEMU option list: commas are doubled,
+/// so that `My,VM` becomes `My,,VM`. Other characters, `=` included, have no special meaning once
+/// in the value of a `key=value` pair.
+///
+/// Escaping is needed for every value that goes through QEMU's option parser:
+///
+/// - the `key=value` options of `-drive`, `-object`, `-netdev`, `-fsdev`, `-machine`, `-name`,
+///   `-semihosting-config`, `-display`, `-spice`, ...
+/// - `unix:` character device paths, used by `-qmp`, `-monitor` and `-serial`, which are followed
+///   by their own options
+///
+/// Values passed as standalone arguments (`-kernel <path>`, `-serial file:<path>`, ...) must not
+/// be escaped.
+pub fn escape(value: &str) -> String {
+    value.replace(",", ",,")
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,7 +1,39 @@
 //! QEMU machine options.
 
 use error::{InitError, Result};
-use std::collections::HashMap;
+
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Target architectures emulated by the `qemu-system-<arch>` executables.
+pub enum Arch {
+    X86_64,
+    I386,
+    Aarch64,
+    Arm,
+    Riscv64,
+    Riscv32,
+    Ppc64,
+    S390x,
+}
+
+impl Arch {
+    /// Return the name of the QEMU executable emulating this architecture.
+    pub fn executable(&self) -> String {
+        let arch = match *self {
+            Arch::X86_64 => "x86_64",
+            Arch::I386 => "i386",
+            Arch::Aarch64 => "aarch64",
+            Arch::Arm => "arm",
+            Arch::Riscv64 => "riscv64",
+            Arch::Riscv32 => "riscv32",
+            Arch::Ppc64 => "ppc64",
+            Arch::S390x => "s390x",
+        };
+
+        format!("qemu-system-{}", arch)
+    }
+}
 
 /// Represents the CPU settings of the emulated SMP system.
 pub struct Processors {
@@ -34,9 +66,9 @@ impl Processors {
 
         Ok(Processors {
             ncpus: None,
-            cores: cores,
-            threads: threads,
-            sockets: sockets,
+            cores,
+            threads,
+            sockets,
             maxcpus: None,
         })
     }
@@ -50,34 +82,77 @@ impl Processors {
 
 impl super::IntoArguments for Processors {
     fn into_arguments(self) -> Vec<String> {
-        let mut opts = HashMap::new();
+        let mut opts = Vec::new();
 
         if let Some(ncpus) = self.ncpus {
-            opts.insert(String::from("cpus"), format!("{}", ncpus));
+            opts.push(format!("cpus={}", ncpus));
         } else {
             if let Some(cores) = self.cores {
-                opts.insert(String::from("cores"), format!("{}", cores));
+                opts.push(format!("cores={}", cores));
             }
             if let Some(threads) = self.threads {
-                opts.insert(String::from("threads"), format!("{}", threads));
+                opts.push(format!("threads={}", threads));
             }
             if let Some(sockets) = self.sockets {
-                opts.insert(String::from("sockets"), format!("{}", sockets));
+                opts.push(format!("sockets={}", sockets));
             }
         }
 
         if let Some(maxcpus) = self.maxcpus {
-            opts.insert(String::from("maxcpus"), maxcpus.to_string());
+            opts.push(format!("maxcpus={}", maxcpus));
         }
 
-        let mut settings = opts.into_iter()
-            .map(|(opt, val)| format!("{}={},", opt, val))
-            .fold(String::new(), |mut a, b| { a.push_str(&b); a });
+        vec![String::from("-smp"), opts.join(",")]
+    }
+}
 
-        // Remove trailing coma.
-        settings.pop();
+/// Represents the CPU model presented to the guest, along with its feature flags.
+pub struct Cpu {
+    model: String,
+    features: Vec<(bool, String)>,
+}
 
-        vec![String::from("-smp"), settings]
+impl Cpu {
+    /// Use the specified CPU model, such as `qemu64` or `Skylake-Server`.
+    pub fn new<S: Into<String>>(model: S) -> Cpu {
+        Cpu {
+            model: model.into(),
+            features: Vec::new(),
+        }
+    }
+
+    /// Pass the host CPU through to the guest. Requires an accelerator such as KVM.
+    pub fn host() -> Cpu {
+        Cpu::new("host")
+    }
+
+    /// Enable every feature supported by the accelerator in use.
+    pub fn max() -> Cpu {
+        Cpu::new("max")
+    }
+
+    /// Enable the specified CPU feature, for example `vmx` for nested virtualization.
+    pub fn enable<S: Into<String>>(mut self, feature: S) -> Self {
+        self.features.push((true, feature.into()));
+        self
+    }
+
+    /// Disable the specified CPU feature.
+    pub fn disable<S: Into<String>>(mut self, feature: S) -> Self {
+        self.features.push((false, feature.into()));
+        self
+    }
+}
+
+impl super::IntoArguments for Cpu {
+    fn into_arguments(self) -> Vec<String> {
+        let mut settings = self.model;
+
+        for (enabled, feature) in self.features {
+            settings.push_str(&format!(",{}{}", if enabled { "+" } else { "-" }, feature));
+        }
+
+        vec![String::from("-cpu"), settings]
     }
 }
 
@@ -86,6 +161,10 @@ pub struct Memory {
     size: u64,
     slots: Option<u8>,
     maxmem: Option<u64>,
+    mem_path: Option<PathBuf>,
+    prealloc: bool,
+    share: bool,
+    legacy_mem_path: bool,
 }
 
 impl Memory {
@@ -93,9 +172,13 @@ impl Memory {
     /// will not be available.
     pub fn new(size: u64) -> Memory {
         Memory {
-            size: size,
+            size,
             slots: None,
             maxmem: None,
+            mem_path: None,
+            prealloc: false,
+            share: false,
+            legacy_mem_path: false,
         }
     }
 
@@ -104,22 +187,411 @@ impl Memory {
     /// page size.
     pub fn with(size: u64, slots: u8, maxmem: u64) -> Memory {
         Memory {
-            size: size,
+            size,
             slots: Some(slots),
             maxmem: Some(maxmem),
+            mem_path: None,
+            prealloc: false,
+            share: false,
+            legacy_mem_path: false,
         }
     }
+
+    /// Back the guest RAM by files created at the specified path, usually a hugetlbfs mount
+    /// point such as `/dev/hugepages`.
+    pub fn set_mem_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.mem_path = Some(path.into());
+        self
+    }
+
+    /// Specify wether the whole guest RAM should be allocated when QEMU starts.
+    pub fn set_prealloc(mut self, prealloc: bool) -> Self {
+        self.prealloc = prealloc;
+        self
+    }
+
+    /// Specify wether the file backing the guest RAM should be mapped as shared memory, as
+    /// required by vhost-user devices.
+    pub fn set_share(mut self, share: bool) -> Self {
+        self.share = share;
+        self
+    }
+
+    /// Specify wether the memory path should be passed using the `-mem-path` and `-mem-prealloc`
+    /// options, rather than a `memory-backend-file` object which requires QEMU 5.0 or newer.
+    /// The legacy options can't map the memory as shared.
+    pub fn use_legacy_mem_path(mut self, legacy: bool) -> Self {
+        self.legacy_mem_path = legacy;
+        self
+    }
 }
 
 impl super::IntoArguments for Memory {
     fn into_arguments(self) -> Vec<String> {
         let mut settings = format!("size={}", self.size);
 
-        if self.slots.is_some() && self.maxmem.is_some() {
-            settings.push_str(format!(",slots={}", self.slots.unwrap()).as_str());
-            settings.push_str(format!(",maxmem={}", self.maxmem.unwrap()).as_str());
+        if let (Some(slots), Some(maxmem)) = (self.slots, self.maxmem) {
+            settings.push_str(format!(",slots={}", slots).as_str());
+            settings.push_str(format!(",maxmem={}", maxmem).as_str());
+        }
+
+        let mut args = vec![String::from("-m"), settings];
+
+        if let Some(path) = self.mem_path {
+            if self.legacy_mem_path {
+                args.push(String::from("-mem-path"));
+                args.push(path.display().to_string());
+
+                if self.prealloc {
+                    args.push(String::from("-mem-prealloc"));
+                }
+            } else {
+                let mut backend = format!("memory-backend-file,id=mem,size={}M,mem-path={}",
+                    self.size, super::escape(&path.display().to_string()));
+
+                if self.prealloc {
+                    backend.push_str(",prealloc=on");
+                }
+
+                if self.share {
+                    backend.push_str(",share=on");
+                }
+
+                args.extend(vec![
+                    String::from("-object"), backend,
+                    String::from("-machine"), String::from("memory-backend=mem"),
+                ]);
+            }
+        }
+
+        args
+    }
+}
+
+/// Represents a NUMA node: a range of CPUs and the amount of RAM in MiB local to them.
+pub struct NumaNode {
+    cpus: RangeInclusive<u8>,
+    mem: u64,
+}
+
+impl NumaNode {
+    /// Define a node made of the CPUs in the `cpus` range, with `mem` MiB of RAM.
+    pub fn new(cpus: RangeInclusive<u8>, mem: u64) -> NumaNode {
+        NumaNode {
+            cpus,
+            mem,
+        }
+    }
+}
+
+/// Represents the NUMA topology of the guest. The memory of the nodes is not checked against
+/// the `Memory` settings, QEMU refuses to start if their sum differs from the RAM size.
+pub struct Numa {
+    nodes: Vec<NumaNode>,
+}
+
+impl Numa {
+    /// Define a topology made of the specified nodes, numbered in order. Returns an error if
+    /// there is no node, or if the CPU ranges of two nodes overlap.
+    pub fn new(nodes: Vec<NumaNode>) -> Result<Numa> {
+        if nodes.is_empty() {
+            return Err(InitError::InvalidConfig{msg: String::from("at least one NUMA node must be defined")}.into());
+        }
+
+        for (i, a) in nodes.iter().enumerate() {
+            for b in &nodes[i + 1..] {
+                if a.cpus.start() <= b.cpus.end() && b.cpus.start() <= a.cpus.end() {
+                    let msg = format!("NUMA nodes CPU ranges overlap: {:?} and {:?}", a.cpus, b.cpus);
+                    return Err(InitError::InvalidConfig{msg}.into());
+                }
+            }
+        }
+
+        Ok(Numa {
+            nodes,
+        })
+    }
+}
+
+impl super::IntoArguments for Numa {
+    fn into_arguments(self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        for (id, node) in self.nodes.into_iter().enumerate() {
+            let cpus = if node.cpus.start() == node.cpus.end() {
+                node.cpus.start().to_string()
+            } else {
+                format!("{}-{}", node.cpus.start(), node.cpus.end())
+            };
+
+            args.push(String::from("-numa"));
+            args.push(format!("node,nodeid={},cpus={},mem={}", id, cpus, node.mem));
+        }
+
+        args
+    }
+}
+
+/// Hardware accelerators QEMU can use to run the guest.
+pub enum Accel {
+    Kvm,
+    Tcg,
+    Hvf,
+    Whpx,
+}
+
+/// Represents the emulated machine model and the accelerators used to run it.
+pub struct MachineType {
+    name: String,
+    accels: Vec<Accel>,
+}
+
+impl MachineType {
+    /// Emulate the specified machine model, such as `pc`, `q35` or `virt`.
+    pub fn new<S: Into<String>>(name: S) -> MachineType {
+        MachineType {
+            name: name.into(),
+            accels: Vec::new(),
+        }
+    }
+
+    /// Add an accelerator to use. When several are added, QEMU uses the first one that is
+    /// available on the host, in the order they were added.
+    pub fn add_accel(mut self, accel: Accel) -> Self {
+        self.accels.push(accel);
+        self
+    }
+}
+
+impl super::IntoArguments for MachineType {
+    fn into_arguments(self) -> Vec<String> {
+        let mut settings = format!("type={}", self.name);
+
+        if !self.accels.is_empty() {
+            let accels = self.accels.into_iter()
+                .map(|accel| match accel {
+                    Accel::Kvm => "kvm",
+                    Accel::Tcg => "tcg",
+                    Accel::Hvf => "hvf",
+                    Accel::Whpx => "whpx",
+                })
+                .collect::<Vec<_>>();
+
+            settings.push_str(&format!(",accel={}", accels.join(":")));
+        }
+
+        vec![String::from("-machine"), settings]
+    }
+}
+
+/// Where semihosting calls made by the guest are serviced.
+pub enum SemihostingTarget {
+    Native,
+    Gdb,
+    Auto,
+}
+
+/// Represents ARM/RISC-V semihosting settings, letting bare-metal programs use the host for
+/// console I/O and to report their exit code.
+pub struct SemihostingConfig {
+    target: SemihostingTarget,
+    args: Vec<String>,
+}
+
+impl SemihostingConfig {
+    /// Enable semihosting, serviced by QEMU itself.
+    pub fn new() -> SemihostingConfig {
+        SemihostingConfig {
+            target: SemihostingTarget::Native,
+            args: Vec::new(),
+        }
+    }
+
+    /// Set where semihosting calls are serviced.
+    pub fn set_target(mut self, target: SemihostingTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Add a command line argument passed to the guest program. The first argument is
+    /// conventionally the program name.
+    pub fn add_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+}
+
+impl Default for SemihostingConfig {
+    fn default() -> SemihostingConfig {
+        SemihostingConfig::new()
+    }
+}
+
+impl super::IntoArguments for SemihostingConfig {
+    fn into_arguments(self) -> Vec<String> {
+        let target = match self.target {
+            SemihostingTarget::Native => "native",
+            SemihostingTarget::Gdb => "gdb",
+            SemihostingTarget::Auto => "auto",
+        };
+
+        let mut settings = format!("enable=on,target={}", target);
+
+        for arg in self.args {
+            settings.push_str(&format!(",arg={}", super::escape(&arg)));
+        }
+
+        vec![String::from("-semihosting-config"), settings]
+    }
+}
+
+/// Starting value of the guest's real time clock.
+pub enum RtcBase {
+    Utc,
+    Localtime,
+
+    /// An explicit date, formatted as `2006-06-17T16:01:21` or `2006-06-17`.
+    Date(String),
+}
+
+/// Time source driving the guest's real time clock.
+pub enum RtcClock {
+    /// The host system time, following its adjustments.
+    Host,
+
+    /// A monotonic host clock, unaffected by host time changes.
+    Rt,
+
+    /// The virtual clock, which stops along with the guest.
+    Vm,
+}
+
+/// Represents the real time clock settings.
+pub struct Rtc {
+    base: RtcBase,
+    clock: Option<RtcClock>,
+    drift_fix: bool,
+}
+
+impl Rtc {
+    /// Define a real time clock starting at the current UTC time, the recommended setting for
+    /// Linux guests.
+    pub fn new() -> Rtc {
+        Rtc {
+            base: RtcBase::Utc,
+            clock: None,
+            drift_fix: false,
+        }
+    }
+
+    /// Set the starting value of the clock.
+    pub fn set_base(mut self, base: RtcBase) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Set the time source of the clock.
+    pub fn set_clock(mut self, clock: RtcClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Specify wether QEMU should catch up on timer interrupts missed by the guest, which keeps
+    /// the clock of Windows guests accurate.
+    pub fn set_drift_fix(mut self, drift_fix: bool) -> Self {
+        self.drift_fix = drift_fix;
+        self
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Rtc {
+        Rtc::new()
+    }
+}
+
+impl super::IntoArguments for Rtc {
+    fn into_arguments(self) -> Vec<String> {
+        let mut settings = format!("base={}", match self.base {
+            RtcBase::Utc => String::from("utc"),
+            RtcBase::Localtime => String::from("localtime"),
+            RtcBase::Date(date) => date,
+        });
+
+        if let Some(clock) = self.clock {
+            settings.push_str(match clock {
+                RtcClock::Host => ",clock=host",
+                RtcClock::Rt => ",clock=rt",
+                RtcClock::Vm => ",clock=vm",
+            });
+        }
+
+        if self.drift_fix {
+            settings.push_str(",driftfix=slew");
+        }
+
+        vec![String::from("-rtc"), settings]
+    }
+}
+
+/// Name of the guest, shown in the window title and in the VNC server name.
+pub struct Name {
+    name: String,
+}
+
+impl Name {
+    /// Use the specified name for the guest. It may contain any character.
+    pub fn new<S: Into<String>>(name: S) -> Name {
+        Name {
+            name: name.into(),
+        }
+    }
+}
+
+impl super::IntoArguments for Name {
+    fn into_arguments(self) -> Vec<String> {
+        // Use the `guest=` key explicitly, QEMU would otherwise take a name containing `=` for an
+        // option of its own.
+        vec![String::from("-name"), format!("guest={}", super::escape(&self.name))]
+    }
+}
+
+/// Memory balloon device, letting the host reclaim unused guest memory at runtime. The size of the
+/// balloon is controlled through `qmp::QmpClient::set_balloon_target`.
+pub struct Balloon {
+    deflate_on_oom: bool,
+}
+
+impl Balloon {
+    /// Create a new balloon device.
+    pub fn new() -> Balloon {
+        Balloon {
+            deflate_on_oom: false,
+        }
+    }
+
+    /// Specify wether the guest may take memory back from the balloon when it runs out of memory,
+    /// rather than invoking its OOM killer.
+    pub fn set_deflate_on_oom(mut self, deflate_on_oom: bool) -> Self {
+        self.deflate_on_oom = deflate_on_oom;
+        self
+    }
+}
+
+impl Default for Balloon {
+    fn default() -> Balloon {
+        Balloon::new()
+    }
+}
+
+impl super::IntoArguments for Balloon {
+    fn into_arguments(self) -> Vec<String> {
+        let mut device = String::from("virtio-balloon");
+
+        if self.deflate_on_oom {
+            device.push_str(",deflate-on-oom=on");
         }
 
-        vec![String::from("-m"), settings]
+        vec![String::from("-device"), device]
     }
 }
@@ -0,0 +1,83 @@
+//! QEMU human monitor (HMP).
+
+use error::{MonitorError, Result};
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::os::unix::net::UnixStream;
+
+/// Prompt printed by the monitor when it is ready to accept a command.
+const PROMPT: &str = "(qemu) ";
+
+/// Represents the settings of the QEMU monitor, served on a Unix socket.
+pub struct Monitor {
+    path: PathBuf,
+}
+
+impl Monitor {
+    /// Make the monitor available on the Unix socket at the specified path.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Monitor {
+        Monitor {
+            path: path.into(),
+        }
+    }
+}
+
+impl super::IntoArguments for Monitor {
+    fn into_arguments(self) -> Vec<String> {
+        vec![String::from("-monitor"), format!("unix:{},server,nowait", super::escape(&self.path.display().to_string()))]
+    }
+}
+
+/// Client connected to the monitor socket of a running QEMU instance.
+pub struct MonitorClient {
+    stream: UnixStream,
+}
+
+impl MonitorClient {
+    /// Connect to the monitor listening on the specified Unix socket, and wait for its prompt.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<MonitorClient> {
+        let mut client = MonitorClient {
+            stream: UnixStream::connect(path)?,
+        };
+
+        // Skip the banner printed before the first prompt.
+        client.read_reply()?;
+        Ok(client)
+    }
+
+    /// Send a command to the monitor, such as `info status` or `change vnc password secret`, and
+    /// return its textual reply.
+    pub fn send_command(&mut self, cmd: &str) -> Result<String> {
+        self.stream.write_all(format!("{}\n", cmd).as_bytes())?;
+        self.stream.flush()?;
+
+        let reply = self.read_reply()?;
+
+        // The monitor echoes the command back before its output.
+        let output = match reply.find('\n') {
+            Some(i) => &reply[i + 1..],
+            None => "",
+        };
+
+        Ok(output.trim_end().to_string())
+    }
+
+    /// Read everything sent by the monitor up to its next prompt, which is stripped.
+    fn read_reply(&mut self) -> Result<String> {
+        let mut data = Vec::new();
+        let mut buf = [0; 4096];
+
+        while !data.ends_with(PROMPT.as_bytes()) {
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Err(MonitorError::ConnectionClosed.into());
+            }
+
+            data.extend_from_slice(&buf[..n]);
+        }
+
+        data.truncate(data.len() - PROMPT.len());
+        Ok(String::from_utf8_lossy(&data).replace("\r\n", "\n"))
+    }
+}
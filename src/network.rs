@@ -0,0 +1,197 @@
+//! QEMU network options.
+
+use error::InitError;
+use failure;
+
+use std::fmt;
+use std::str::FromStr;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::hash::{BuildHasher, Hasher};
+use std::collections::hash_map::RandomState;
+
+/// MAC address of a guest network interface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    /// Generate a random locally administered, unicast MAC address.
+    pub fn random() -> MacAddr {
+        // The standard library seeds every RandomState with random keys.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(::std::process::id());
+
+        let bytes = hasher.finish().to_be_bytes();
+        MacAddr([(bytes[0] & 0xfe) | 0x02, bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]])
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = failure::Error;
+
+    /// Parse a MAC address made of six colon-separated hexadecimal octets, such as
+    /// `52:54:00:12:34:56`.
+    fn from_str(s: &str) -> Result<MacAddr, failure::Error> {
+        let invalid = || InitError::InvalidConfig{msg: format!("invalid MAC address: {}", s)};
+
+        let mut addr = [0; 6];
+        let mut octets = s.split(':');
+
+        for byte in addr.iter_mut() {
+            let octet = octets.next().ok_or_else(invalid)?;
+
+            if octet.len() != 2 || !octet.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid().into());
+            }
+
+            *byte = u8::from_str_radix(octet, 16).map_err(|_| invalid())?;
+        }
+
+        if octets.next().is_some() {
+            return Err(invalid().into());
+        }
+
+        Ok(MacAddr(addr))
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let a = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a[0], a[1], a[2], a[3], a[4], a[5])
+    }
+}
+
+/// Transport protocol of a forwarded port.
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Represents a user-mode (SLIRP) network interface, which requires neither root privileges nor
+/// host configuration.
+pub struct UserNetwork {
+    id: String,
+    guest_addr: Option<Ipv4Addr>,
+    mac_addr: Option<MacAddr>,
+    forwards: Vec<(Protocol, u16, u16)>,
+}
+
+impl UserNetwork {
+    /// Create a new user-mode network interface, identified by `id`.
+    pub fn new<S: Into<String>>(id: S) -> UserNetwork {
+        UserNetwork {
+            id: id.into(),
+            guest_addr: None,
+            mac_addr: None,
+            forwards: Vec::new(),
+        }
+    }
+
+    /// Set the address given to the guest by the built-in DHCP server, which is also the
+    /// destination of forwarded ports.
+    pub fn set_guest_addr(mut self, addr: Ipv4Addr) -> Self {
+        self.guest_addr = Some(addr);
+        self
+    }
+
+    /// Set the MAC address of the guest's network card.
+    pub fn set_mac_addr(mut self, mac: MacAddr) -> Self {
+        self.mac_addr = Some(mac);
+        self
+    }
+
+    /// Forward connections made to `host_port` on the host to `guest_port` in the guest.
+    pub fn add_forward(mut self, host_port: u16, guest_port: u16, protocol: Protocol) -> Self {
+        self.forwards.push((protocol, host_port, guest_port));
+        self
+    }
+}
+
+impl super::IntoArguments for UserNetwork {
+    fn into_arguments(self) -> Vec<String> {
+        let id = super::escape(&self.id);
+        let mut opts = vec![String::from("user"), format!("id={}", id)];
+
+        let guest_addr = match self.guest_addr {
+            Some(addr) => {
+                opts.push(format!("dhcpstart={}", addr));
+                addr.to_string()
+            },
+            None => String::new(),
+        };
+
+        for (protocol, host_port, guest_port) in self.forwards {
+            let protocol = match protocol {
+                Protocol::Tcp => "tcp",
+                Protocol::Udp => "udp",
+            };
+
+            opts.push(format!("hostfwd={}::{}-{}:{}", protocol, host_port, guest_addr, guest_port));
+        }
+
+        let mut device = format!("virtio-net,netdev={}", id);
+        if let Some(mac) = self.mac_addr {
+            device.push_str(&format!(",mac={}", mac));
+        }
+
+        vec![
+            String::from("-netdev"), opts.join(","),
+            String::from("-device"), device,
+        ]
+    }
+}
+
+/// Represents a network interface attached to a host bridge through the QEMU bridge helper.
+pub struct BridgeInterface {
+    id: String,
+    bridge: String,
+    helper: Option<PathBuf>,
+    mac_addr: Option<MacAddr>,
+}
+
+impl BridgeInterface {
+    /// Create a new network interface identified by `id`, attached to the host bridge named
+    /// `bridge` (e.g. `br0`). The bridge must be allowed in the helper's `bridge.conf`.
+    pub fn new<S: Into<String>, B: Into<String>>(id: S, bridge: B) -> BridgeInterface {
+        BridgeInterface {
+            id: id.into(),
+            bridge: bridge.into(),
+            helper: None,
+            mac_addr: None,
+        }
+    }
+
+    /// Set the path of the bridge helper, when it is not installed at QEMU's default location.
+    pub fn set_helper<P: Into<PathBuf>>(mut self, helper: P) -> Self {
+        self.helper = Some(helper.into());
+        self
+    }
+
+    /// Set the MAC address of the guest's network card.
+    pub fn set_mac_addr(mut self, mac: MacAddr) -> Self {
+        self.mac_addr = Some(mac);
+        self
+    }
+}
+
+impl super::IntoArguments for BridgeInterface {
+    fn into_arguments(self) -> Vec<String> {
+        let id = super::escape(&self.id);
+        let mut netdev = format!("bridge,id={},br={}", id, super::escape(&self.bridge));
+
+        if let Some(helper) = self.helper {
+            netdev.push_str(&format!(",helper={}", super::escape(&helper.display().to_string())));
+        }
+
+        let mut device = format!("virtio-net,netdev={}", id);
+        if let Some(mac) = self.mac_addr {
+            device.push_str(&format!(",mac={}", mac));
+        }
+
+        vec![
+            String::from("-netdev"), netdev,
+            String::from("-device"), device,
+        ]
+    }
+}
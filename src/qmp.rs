@@ -0,0 +1,144 @@
+//! QEMU Machine Protocol client.
+
+use error::{QmpError, Result};
+use serde_json::{self, Value};
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::os::unix::net::UnixStream;
+
+/// Client connected to the QMP socket of a running QEMU instance.
+pub struct QmpClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    timeout: Option<Duration>,
+}
+
+impl QmpClient {
+    /// Connect to the QMP server listening on the specified Unix socket, and negociate the
+    /// capabilities so that commands can be executed right away.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<QmpClient> {
+        QmpClient::negociate(UnixStream::connect(path)?, None)
+    }
+
+    /// Connect to the QMP server listening on the specified Unix socket, like `connect`, but give
+    /// up if the negociation takes longer than `timeout`. QEMU serves a single QMP client at a
+    /// time, so others wait for their greeting. Later commands are given the same time to
+    /// complete, see `set_timeout`.
+    pub fn connect_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<QmpClient> {
+        let deadline = Instant::now() + timeout;
+
+        let mut client = QmpClient::negociate(UnixStream::connect(path)?, Some(deadline))?;
+        client.timeout = Some(timeout);
+
+        Ok(client)
+    }
+
+    /// Read the greeting of the server, and negociate the capabilities before `deadline`.
+    fn negociate(stream: UnixStream, deadline: Option<Instant>) -> Result<QmpClient> {
+        let mut client = QmpClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            timeout: None,
+        };
+
+        // The server greets every new client with a banner describing its version.
+        let greeting = client.read_message(deadline)?;
+        if greeting.get("QMP").is_none() {
+            return Err(QmpError::Protocol{msg: format!("unexpected greeting: {}", greeting)}.into());
+        }
+
+        client.execute_before("qmp_capabilities", None, deadline)?;
+        Ok(client)
+    }
+
+    /// Give up on commands taking longer than `timeout` to complete, or wait for them
+    /// indefinitely if `timeout` is `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Execute the specified QMP command with optional arguments, and return its result.
+    /// Asynchronous events received while waiting for the response are discarded.
+    pub fn execute(&mut self, command: &str, args: Option<Value>) -> Result<Value> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.execute_before(command, args, deadline)
+    }
+
+    /// Execute the specified QMP command, giving up if no response was received by `deadline`.
+    fn execute_before(&mut self, command: &str, args: Option<Value>, deadline: Option<Instant>) -> Result<Value> {
+        let mut request = json!({ "execute": command });
+        if let Some(args) = args {
+            request["arguments"] = args;
+        }
+
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+
+        self.set_deadline(deadline)?;
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+
+        loop {
+            let mut response = self.read_message(deadline)?;
+
+            if let Some(ret) = response.get_mut("return") {
+                return Ok(ret.take());
+            }
+
+            if let Some(error) = response.get("error") {
+                return Err(QmpError::Command {
+                    class: error["class"].as_str().unwrap_or_default().to_string(),
+                    desc: error["desc"].as_str().unwrap_or_default().to_string(),
+                }.into());
+            }
+
+            if response.get("event").is_none() {
+                return Err(QmpError::Protocol{msg: format!("unexpected message: {}", response)}.into());
+            }
+        }
+    }
+
+    /// Ask the guest to resize its memory to `mib` MiB, by inflating or deflating the balloon
+    /// device. See `machine::Balloon`.
+    pub fn set_balloon_target(&mut self, mib: u64) -> Result<()> {
+        self.execute("balloon", Some(json!({ "value": mib * 1024 * 1024 })))?;
+        Ok(())
+    }
+
+    /// Read the next JSON message sent by the server, giving up at `deadline`.
+    fn read_message(&mut self, deadline: Option<Instant>) -> Result<Value> {
+        self.set_deadline(deadline)?;
+
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(QmpError::Protocol{msg: String::from("connection closed by QEMU")}.into());
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Make the reads and writes on the socket fail once `deadline` is reached.
+    fn set_deadline(&self, deadline: Option<Instant>) -> Result<()> {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                if remaining == Duration::from_millis(0) {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "QMP server timed out").into());
+                }
+
+                Some(remaining)
+            },
+            None => None,
+        };
+
+        // Both halves of the client share the same socket, and its timeouts.
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+//! QEMU serial port options.
+
+use std::path::PathBuf;
+
+/// Represents where the guest's serial console is redirected.
+pub enum Serial {
+    None,
+    Stdio,
+    File(PathBuf),
+    TcpServer {
+        host: String,
+        port: u16,
+        wait: bool,
+    },
+    UnixSocket(PathBuf),
+}
+
+impl super::IntoArguments for Serial {
+    fn into_arguments(self) -> Vec<String> {
+        let mut args = vec![String::from("-serial")];
+
+        args.push(match self {
+            Serial::None => String::from("none"),
+            Serial::Stdio => String::from("stdio"),
+            Serial::File(path) => format!("file:{}", path.display()),
+            Serial::TcpServer{host, port, wait} => {
+                let mut param = format!("telnet:{}:{},server", host, port);
+
+                // Unless told to wait, QEMU starts the guest before a client connects.
+                if !wait {
+                    param.push_str(",nowait");
+                }
+
+                param
+            },
+            Serial::UnixSocket(path) => format!("unix:{},server,nowait", super::escape(&path.display().to_string())),
+        });
+
+        args
+    }
+}
@@ -0,0 +1,81 @@
+//! Host folders shared with the guest.
+
+use std::path::PathBuf;
+
+/// How the guest's file attributes and permissions are stored on the host.
+pub enum SecurityModel {
+    /// Files are created with the guest's credentials. Requires QEMU to run as root.
+    Passthrough,
+
+    /// Guest credentials are stored in extended attributes of the host files.
+    MappedXattr,
+
+    /// Like passthrough, but failures to set the guest's credentials are ignored.
+    None,
+}
+
+/// Represents a host folder shared with the guest using virtio-9p.
+pub struct SharedFolder {
+    id: String,
+    path: PathBuf,
+    mount_tag: Option<String>,
+    security_model: SecurityModel,
+    read_only: bool,
+}
+
+impl SharedFolder {
+    /// Share the host folder at `path`, identified by `id`. The guest mounts it using its mount
+    /// tag, which defaults to the id.
+    pub fn new<S: Into<String>, P: Into<PathBuf>>(id: S, path: P) -> SharedFolder {
+        SharedFolder {
+            id: id.into(),
+            path: path.into(),
+            mount_tag: None,
+            security_model: SecurityModel::MappedXattr,
+            read_only: false,
+        }
+    }
+
+    /// Set the tag the guest uses to mount the folder, as in `mount -t 9p <tag> /mnt`.
+    pub fn set_mount_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.mount_tag = Some(tag.into());
+        self
+    }
+
+    /// Set the security model of the share. Defaults to `SecurityModel::MappedXattr`.
+    pub fn set_security_model(mut self, model: SecurityModel) -> Self {
+        self.security_model = model;
+        self
+    }
+
+    /// Specify wether the guest should only be allowed to read from the folder.
+    pub fn set_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+}
+
+impl super::IntoArguments for SharedFolder {
+    fn into_arguments(self) -> Vec<String> {
+        let security_model = match self.security_model {
+            SecurityModel::Passthrough => "passthrough",
+            SecurityModel::MappedXattr => "mapped-xattr",
+            SecurityModel::None => "none",
+        };
+
+        let id = super::escape(&self.id);
+        let mount_tag = self.mount_tag.as_ref().map_or_else(|| id.clone(), |tag| super::escape(tag));
+
+        let mut fsdev = format!("local,id={},path={},security_model={}",
+            id, super::escape(&self.path.display().to_string()), security_model);
+
+        if self.read_only {
+            fsdev.push_str(",readonly=on");
+        }
+
+        vec![
+            String::from("-fsdev"), fsdev,
+            String::from("-device"), format!("virtio-9p-pci,fsdev={},mount_tag={}", id, mount_tag),
+        ]
+    }
+}
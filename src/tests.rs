@@ -1 +1,1147 @@
 //! Unit tests.
+
+use std::env;
+use std::fs;
+use std::thread;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::os::unix::net::UnixListener;
+
+use super::{Builder, IntoArguments, RunStatus, StartupCheck};
+use error::{ImageError, InitError, QmpError};
+use qmp::QmpClient;
+use audio::{self, Audio, Card};
+use display::{Display, Vnc, Spice};
+use boot::{DirectBoot, Firmware, Boot, BootDevice};
+use drive::{Drive, Interface, Cache};
+use host::{self, Version};
+use image::{self, Format, Image, Snapshot};
+use monitor::{Monitor, MonitorClient};
+use serial::Serial;
+use share::{SharedFolder, SecurityModel};
+use network::{UserNetwork, BridgeInterface, Protocol, MacAddr};
+use machine::{Arch, Balloon, Name, Rtc, RtcBase, RtcClock, Processors, Memory, Cpu, Numa, NumaNode, MachineType, Accel, SemihostingConfig, SemihostingTarget};
+
+/// Raw command line arguments, used to feed arbitrary options to the builder.
+struct Raw(Vec<&'static str>);
+
+impl IntoArguments for Raw {
+    fn into_arguments(self) -> Vec<String> {
+        self.0.into_iter().map(String::from).collect()
+    }
+}
+
+/// Return a path in the temporary directory that is unique to this test process.
+fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("qemu-rs-{}-{}", ::std::process::id(), name));
+    let _ = fs::remove_file(&path);
+
+    path
+}
+
+/// Serve a single client on the specified Unix socket, sending `greeting` first and then
+/// answering every received line with the next of the `replies`. Returns the received lines.
+fn fake_server(path: &PathBuf, greeting: &'static str, replies: Vec<&'static str>) -> thread::JoinHandle<Vec<String>> {
+    let listener = UnixListener::bind(path).unwrap();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut received = Vec::new();
+
+        stream.write_all(greeting.as_bytes()).unwrap();
+
+        for reply in replies {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            received.push(line.trim_end().to_string());
+
+            stream.write_all(reply.as_bytes()).unwrap();
+        }
+
+        received
+    })
+}
+
+#[test]
+fn set_arguments() {
+    let builder = Builder::new("sh").unwrap()
+        .set(Memory::new(512))
+        .set(Processors::new(2));
+
+    assert_eq!(builder.arguments(), vec!["-m", "size=512", "-smp", "cpus=2"]);
+}
+
+#[test]
+fn command_line() {
+    let builder = Builder::new("/bin/sh").unwrap()
+        .set(Display::Vnc(Vnc::new("127.0.0.1", 1)))
+        .set(Memory::new(1024));
+
+    let expected = vec!["/bin/sh", "-display", "vnc=127.0.0.1:1", "-m", "size=1024"];
+
+    assert_eq!(builder.args(), expected);
+    assert_eq!(builder.args(), expected);
+}
+
+#[test]
+fn processors_order() {
+    let cpus = Processors::with(Some(2), Some(2), Some(1)).unwrap();
+    assert_eq!(cpus.into_arguments(), vec!["-smp", "cores=2,threads=2,sockets=1"]);
+
+    let cpus = Processors::new(4).set_max_cpus(8);
+    assert_eq!(cpus.into_arguments(), vec!["-smp", "cpus=4,maxcpus=8"]);
+}
+
+#[test]
+fn debug_threads() {
+    let builder = Builder::new("sh").unwrap()
+        .debug_threads(true);
+
+    assert_eq!(builder.arguments(), vec!["-name", "debug-threads=on"]);
+
+    let builder = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-name", "guest=vm0"]))
+        .debug_threads(true);
+
+    assert_eq!(builder.arguments(), vec!["-name", "guest=vm0,debug-threads=on"]);
+}
+
+#[test]
+fn no_user_config() {
+    let builder = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-m", "size=128"]))
+        .no_user_config();
+
+    assert_eq!(builder.arguments(), vec!["-no-user-config", "-m", "size=128"]);
+}
+
+#[test]
+fn semihosting() {
+    let config = SemihostingConfig::new();
+    assert_eq!(config.into_arguments(), vec!["-semihosting-config", "enable=on,target=native"]);
+
+    let builder = Builder::new("sh").unwrap()
+        .semihosting(SemihostingConfig::new()
+            .set_target(SemihostingTarget::Gdb)
+            .add_arg("firmware.elf")
+            .add_arg("--filter=a,b"));
+
+    assert_eq!(builder.arguments(), vec![
+        "-semihosting-config",
+        "enable=on,target=gdb,arg=firmware.elf,arg=--filter=a,,b",
+    ]);
+}
+
+#[test]
+fn exit_on_guest_exit() {
+    let builder = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-m", "size=128"]))
+        .exit_on_guest_exit();
+
+    assert_eq!(builder.arguments(), vec!["-m", "size=128", "-no-reboot"]);
+}
+
+#[test]
+#[ignore] // Requires qemu-system-arm.
+fn semihosting_exit_code() {
+    // Bare-metal Arm program calling SYS_EXIT_EXTENDED to exit with code 3:
+    //     mov r0, #0x20
+    //     add r1, pc, #4
+    //     svc #0x123456
+    //     b .
+    //     .word 0x20026 @ ADP_Stopped_ApplicationExit
+    //     .word 3
+    let program: Vec<u8> = [0xe3a00020u32, 0xe28f1004, 0xef123456, 0xeafffffe, 0x20026, 3].iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .collect();
+
+    let path = temp_path("semihosting.bin");
+    fs::write(&path, program).unwrap();
+
+    let mut instance = Builder::for_arch(Arch::Arm).unwrap()
+        .set(MachineType::new("virt"))
+        .set(Cpu::new("cortex-a15"))
+        .set(Display::None)
+        .set(DirectBoot::new(path.clone()))
+        .semihosting(SemihostingConfig::new())
+        .exit_on_guest_exit()
+        .run_timeout(Duration::from_secs(30))
+        .start().unwrap();
+
+    let status = instance.wait().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(status.exit_status().code(), Some(3));
+}
+
+#[test]
+fn run_timeout() {
+    let start = Instant::now();
+    let mut instance = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["10"]))
+        .run_timeout(Duration::from_millis(100))
+        .start().unwrap();
+
+    let status = instance.wait().unwrap();
+    match status {
+        RunStatus::TimedOut(status) => assert!(!status.success()),
+        _ => panic!("unexpected status: {:?}", status),
+    }
+
+    // The timeout is reported every time, not only by the call that reaped the process.
+    assert_eq!(instance.wait().unwrap(), status);
+    assert_eq!(instance.try_wait().unwrap(), Some(status));
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "exit 0"]))
+        .run_timeout(Duration::from_secs(10))
+        .start().unwrap();
+
+    assert!(instance.wait().unwrap().exit_status().success());
+}
+
+#[test]
+fn run_timeout_after_exit() {
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "exit 0"]))
+        .run_timeout(Duration::from_millis(100))
+        .start().unwrap();
+
+    // The process exits right away, but is only waited on after the limit.
+    thread::sleep(Duration::from_millis(300));
+
+    assert!(instance.wait().unwrap().exit_status().success());
+}
+
+#[test]
+fn instance_lifecycle() {
+    let mut instance = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["10"]))
+        .start().unwrap();
+
+    assert!(instance.pid() > 0);
+    assert!(instance.try_wait().unwrap().is_none());
+
+    instance.kill().unwrap();
+    assert!(!instance.wait().unwrap().exit_status().success());
+}
+
+#[test]
+fn qmp_arguments() {
+    let builder = Builder::new("sh").unwrap()
+        .qmp("/tmp/vm0.qmp");
+
+    assert_eq!(builder.arguments(), vec!["-qmp", "unix:/tmp/vm0.qmp,server,nowait"]);
+}
+
+#[test]
+fn qmp_client() {
+    let path = temp_path("qmp.sock");
+    let server = fake_server(&path, "{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n", vec![
+        "{\"return\": {}}\n",
+        "{\"event\": \"RESUME\"}\n{\"return\": {\"status\": \"running\", \"running\": true}}\n",
+        "{\"error\": {\"class\": \"GenericError\", \"desc\": \"No such snapshot\"}}\n",
+    ]);
+
+    let mut client = QmpClient::connect(&path).unwrap();
+
+    let status = client.execute("query-status", None).unwrap();
+    assert_eq!(status["status"], "running");
+
+    let err = client.execute("snapshot-load", Some(json!({"job-id": "load0"}))).unwrap_err();
+    match err.downcast_ref::<QmpError>() {
+        Some(QmpError::Command{class, desc}) => {
+            assert_eq!(class, "GenericError");
+            assert_eq!(desc, "No such snapshot");
+        },
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    assert_eq!(server.join().unwrap(), vec![
+        "{\"execute\":\"qmp_capabilities\"}",
+        "{\"execute\":\"query-status\"}",
+        "{\"arguments\":{\"job-id\":\"load0\"},\"execute\":\"snapshot-load\"}",
+    ]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn monitor_arguments() {
+    let monitor = Monitor::new("/tmp/vm0.monitor");
+    assert_eq!(monitor.into_arguments(), vec!["-monitor", "unix:/tmp/vm0.monitor,server,nowait"]);
+}
+
+#[test]
+fn monitor_client() {
+    let path = temp_path("monitor.sock");
+    let server = fake_server(&path, "QEMU 2.11.1 monitor - type 'help' for more information\r\n(qemu) ", vec![
+        "info status\r\nVM status: running\r\n(qemu) ",
+        "change vnc password secret\r\n(qemu) ",
+    ]);
+
+    let mut client = MonitorClient::connect(&path).unwrap();
+
+    assert_eq!(client.send_command("info status").unwrap(), "VM status: running");
+    assert_eq!(client.send_command("change vnc password secret").unwrap(), "");
+
+    assert_eq!(server.join().unwrap(), vec!["info status", "change vnc password secret"]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn drive() {
+    let drive = Drive::new("/var/lib/vms/disk.qcow2")
+        .set_format(Format::Qcow2)
+        .set_interface(Interface::Virtio)
+        .set_read_only(true);
+
+    assert_eq!(drive.into_arguments(), vec![
+        "-drive",
+        "file=/var/lib/vms/disk.qcow2,format=qcow2,if=virtio,readonly=on",
+    ]);
+
+    let drive = Drive::new("/var/lib/vms/a,b.img")
+        .set_format(Format::Raw)
+        .set_cache(Cache::None);
+
+    assert_eq!(drive.into_arguments(), vec!["-drive", "file=/var/lib/vms/a,,b.img,format=raw,cache=none"]);
+}
+
+#[test]
+fn user_network() {
+    let net = UserNetwork::new("net0")
+        .add_forward(2222, 22, Protocol::Tcp);
+
+    assert_eq!(net.into_arguments(), vec![
+        "-netdev", "user,id=net0,hostfwd=tcp::2222-:22",
+        "-device", "virtio-net,netdev=net0",
+    ]);
+
+    let net = UserNetwork::new("net1")
+        .set_guest_addr("10.0.2.20".parse().unwrap())
+        .add_forward(2222, 22, Protocol::Tcp)
+        .add_forward(5353, 53, Protocol::Udp);
+
+    assert_eq!(net.into_arguments(), vec![
+        "-netdev", "user,id=net1,dhcpstart=10.0.2.20,hostfwd=tcp::2222-10.0.2.20:22,hostfwd=udp::5353-10.0.2.20:53",
+        "-device", "virtio-net,netdev=net1",
+    ]);
+}
+
+#[test]
+fn direct_boot() {
+    let boot = DirectBoot::new("/boot/vmlinuz");
+    assert_eq!(boot.into_arguments(), vec!["-kernel", "/boot/vmlinuz"]);
+
+    let boot = DirectBoot::new("/boot/vmlinuz")
+        .set_initrd("/boot/initrd.img");
+
+    assert_eq!(boot.into_arguments(), vec!["-kernel", "/boot/vmlinuz", "-initrd", "/boot/initrd.img"]);
+
+    let boot = DirectBoot::new("/boot/vmlinuz")
+        .set_initrd("/boot/initrd.img")
+        .set_append("console=ttyS0 root=/dev/vda1");
+
+    assert_eq!(boot.into_arguments(), vec![
+        "-kernel", "/boot/vmlinuz",
+        "-initrd", "/boot/initrd.img",
+        "-append", "console=ttyS0 root=/dev/vda1",
+    ]);
+}
+
+#[test]
+fn machine_type() {
+    let machine = MachineType::new("q35")
+        .add_accel(Accel::Kvm)
+        .add_accel(Accel::Tcg);
+
+    assert_eq!(machine.into_arguments(), vec!["-machine", "type=q35,accel=kvm:tcg"]);
+    assert_eq!(MachineType::new("virt").into_arguments(), vec!["-machine", "type=virt"]);
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_resize() {
+    let path = temp_path("resize.img");
+
+    let mut image = Image::new(&path, Format::Raw, 1024 * 1024);
+    image.create().unwrap();
+
+    image.resize(4 * 1024 * 1024).unwrap();
+    assert_eq!(image.size(), 4 * 1024 * 1024);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 4 * 1024 * 1024);
+
+    image.resize_by(-1024 * 1024).unwrap();
+    assert_eq!(image.size(), 3 * 1024 * 1024);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 1024 * 1024);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_convert() {
+    let src_path = temp_path("convert.img");
+    let dest_path = temp_path("convert.qcow2");
+
+    let image = Image::new(&src_path, Format::Raw, 1024 * 1024);
+    image.create().unwrap();
+
+    let converted = image.convert(&dest_path, Format::Qcow2, true).unwrap();
+    assert!(dest_path.exists());
+    assert_eq!(converted.path(), dest_path.as_path());
+    assert_eq!(converted.format(), &Format::Qcow2);
+    assert_eq!(converted.size(), 1024 * 1024);
+
+    fs::remove_file(&src_path).unwrap();
+    fs::remove_file(&dest_path).unwrap();
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_info() {
+    let path = temp_path("info.qcow2");
+
+    Image::new(&path, Format::Qcow2, 64 * 1024 * 1024).create().unwrap();
+
+    let image = Image::info(&path).unwrap();
+    assert_eq!(image.format(), &Format::Qcow2);
+    assert_eq!(image.size(), 64 * 1024 * 1024);
+    assert!(image.actual_size().is_some());
+    assert!(image.backing_file().is_none());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn image_backing_format() {
+    let base = Image::new("/var/lib/vms/base.img", Format::Raw, 1024 * 1024);
+
+    let overlay = Image::with_backing("/var/lib/vms/overlay.qcow2", Format::Qcow2, &base).unwrap();
+    assert_eq!(overlay.size(), 1024 * 1024);
+    assert_eq!(overlay.backing_file(), Some(base.path()));
+    assert_eq!(overlay.backing_format(), Some(&Format::Raw));
+
+    assert!(Image::with_backing("/var/lib/vms/overlay.img", Format::Raw, &base).is_err());
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_backing_file() {
+    let base_path = temp_path("base.img");
+    let overlay_path = temp_path("overlay.qcow2");
+
+    let base = Image::new(&base_path, Format::Raw, 1024 * 1024);
+    base.create().unwrap();
+
+    Image::with_backing(&overlay_path, Format::Qcow2, &base).unwrap().create().unwrap();
+
+    let overlay = Image::info(&overlay_path).unwrap();
+    assert_eq!(overlay.backing_file(), Some(base_path.as_path()));
+    assert_eq!(overlay.backing_format(), Some(&Format::Raw));
+    assert_eq!(overlay.size(), 1024 * 1024);
+
+    fs::remove_file(&base_path).unwrap();
+    fs::remove_file(&overlay_path).unwrap();
+}
+
+#[test]
+fn serial() {
+    assert_eq!(Serial::None.into_arguments(), vec!["-serial", "none"]);
+    assert_eq!(Serial::Stdio.into_arguments(), vec!["-serial", "stdio"]);
+    assert_eq!(Serial::File(PathBuf::from("/tmp/console.log")).into_arguments(), vec!["-serial", "file:/tmp/console.log"]);
+    assert_eq!(Serial::UnixSocket(PathBuf::from("/tmp/console.sock")).into_arguments(), vec!["-serial", "unix:/tmp/console.sock,server,nowait"]);
+
+    let serial = Serial::TcpServer{host: String::from("127.0.0.1"), port: 4444, wait: false};
+    assert_eq!(serial.into_arguments(), vec!["-serial", "telnet:127.0.0.1:4444,server,nowait"]);
+
+    let serial = Serial::TcpServer{host: String::from("127.0.0.1"), port: 4444, wait: true};
+    assert_eq!(serial.into_arguments(), vec!["-serial", "telnet:127.0.0.1:4444,server"]);
+}
+
+#[test]
+fn cpu() {
+    let cpu = Cpu::host()
+        .enable("vmx")
+        .disable("svm");
+
+    assert_eq!(cpu.into_arguments(), vec!["-cpu", "host,+vmx,-svm"]);
+    assert_eq!(Cpu::new("qemu64").into_arguments(), vec!["-cpu", "qemu64"]);
+}
+
+#[test]
+fn firmware() {
+    let firmware = Firmware::Bios(PathBuf::from("/usr/share/seabios/bios.bin"));
+    assert_eq!(firmware.into_arguments(), vec!["-bios", "/usr/share/seabios/bios.bin"]);
+
+    let firmware = Firmware::Pflash {
+        code: PathBuf::from("/usr/share/OVMF/OVMF_CODE.fd"),
+        vars: PathBuf::from("/var/lib/vms/vm0/OVMF_VARS.fd"),
+    };
+
+    assert_eq!(firmware.into_arguments(), vec![
+        "-drive", "if=pflash,format=raw,unit=0,readonly=on,file=/usr/share/OVMF/OVMF_CODE.fd",
+        "-drive", "if=pflash,format=raw,unit=1,file=/var/lib/vms/vm0/OVMF_VARS.fd",
+    ]);
+}
+
+#[test]
+fn boot() {
+    let boot = Boot::new(vec![BootDevice::Cdrom, BootDevice::Disk]).unwrap()
+        .set_reboot_timeout(Duration::from_secs(5));
+
+    assert_eq!(boot.into_arguments(), vec!["-boot", "order=dc,reboot-timeout=5000"]);
+
+    let boot = Boot::new(vec![BootDevice::Disk]).unwrap()
+        .set_menu(true);
+
+    assert_eq!(boot.into_arguments(), vec!["-boot", "order=c,menu=on"]);
+
+    assert!(Boot::new(Vec::new()).is_err());
+}
+
+#[test]
+fn startup_check() {
+    let err = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "echo 'qemu: -bogus: invalid option' >&2; exit 1"]))
+        .startup_check(StartupCheck::ProcessAlive(Duration::from_secs(5)))
+        .start().err().unwrap();
+
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::QemuExited{code, stderr}) => {
+            assert_eq!(*code, Some(1));
+            assert_eq!(stderr, "qemu: -bogus: invalid option");
+        },
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    let mut instance = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["10"]))
+        .startup_check(StartupCheck::ProcessAlive(Duration::from_millis(50)))
+        .start().unwrap();
+
+    assert!(instance.try_wait().unwrap().is_none());
+    instance.kill().unwrap();
+    instance.wait().unwrap();
+}
+
+#[test]
+fn shared_folder() {
+    let share = SharedFolder::new("build", "/home/user/build")
+        .set_security_model(SecurityModel::MappedXattr)
+        .set_read_only(true);
+
+    assert_eq!(share.into_arguments(), vec![
+        "-fsdev", "local,id=build,path=/home/user/build,security_model=mapped-xattr,readonly=on",
+        "-device", "virtio-9p-pci,fsdev=build,mount_tag=build",
+    ]);
+
+    let share = SharedFolder::new("fs0", "/srv/data")
+        .set_mount_tag("data")
+        .set_security_model(SecurityModel::Passthrough);
+
+    assert_eq!(share.into_arguments(), vec![
+        "-fsdev", "local,id=fs0,path=/srv/data,security_model=passthrough",
+        "-device", "virtio-9p-pci,fsdev=fs0,mount_tag=data",
+    ]);
+}
+
+#[test]
+fn spice() {
+    let mut spice = Spice::new(5930);
+    spice.disable_ticketing();
+
+    assert_eq!(Display::Spice(spice).into_arguments(), vec!["-spice", "port=5930,disable-ticketing=on"]);
+
+    let mut spice = Spice::with_tls(5930, 5931);
+    spice.set_password("secret");
+    spice.use_agent(true);
+
+    assert_eq!(Display::Spice(spice).into_arguments(), vec![
+        "-spice", "port=5930,tls-port=5931,password=secret",
+        "-device", "virtio-serial",
+        "-chardev", "spicevmc,id=vdagent,name=vdagent",
+        "-device", "virtserialport,chardev=vdagent,name=com.redhat.spice.0",
+    ]);
+
+    let mut spice = Spice::new(5930);
+    spice.set_password_file("/run/vm,0/spice.pw");
+
+    assert_eq!(Display::Spice(spice).into_arguments(), vec![
+        "-object", "secret,id=spice-pw0,file=/run/vm,,0/spice.pw",
+        "-spice", "port=5930,password-secret=spice-pw0",
+    ]);
+}
+
+#[test]
+fn no_graphic() {
+    assert_eq!(Display::NoGraphic.into_arguments(), vec!["-nographic"]);
+
+    let err = Builder::new("sh").unwrap()
+        .set(Display::NoGraphic)
+        .set(Serial::Stdio)
+        .start().err().unwrap();
+
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::InvalidConfig{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn vnc_tls() {
+    let mut vnc = Vnc::new("0.0.0.0", 0);
+    vnc.use_tls("/etc/pki/qemu", true);
+
+    assert_eq!(Display::Vnc(vnc).into_arguments(), vec![
+        "-object", "tls-creds-x509,id=vnc-tls0,dir=/etc/pki/qemu,endpoint=server,verify-peer=on",
+        "-display", "vnc=0.0.0.0:0,tls-creds=vnc-tls0",
+    ]);
+
+    let mut vnc = Vnc::new("0.0.0.0", 0);
+    vnc.use_tls("/etc/pki/qemu", true);
+    vnc.use_legacy_tls(true);
+
+    assert_eq!(Display::Vnc(vnc).into_arguments(), vec!["-display", "vnc=0.0.0.0:0,tls,x509verify=/etc/pki/qemu"]);
+}
+
+#[test]
+fn mac_addr() {
+    let mac = "52:54:00:AB:cd:0f".parse::<MacAddr>().unwrap();
+    assert_eq!(mac.to_string(), "52:54:00:ab:cd:0f");
+
+    let net = UserNetwork::new("net0").set_mac_addr(mac);
+    assert_eq!(net.into_arguments(), vec!["-netdev", "user,id=net0", "-device", "virtio-net,netdev=net0,mac=52:54:00:ab:cd:0f"]);
+
+    for invalid in &["52:54:zz:12:34:56", "52:54:00:12:34", "52:54:00:12:34:56:78", "5:54:00:12:34:56", "+2:54:00:12:34:56", ""] {
+        let err = invalid.parse::<MacAddr>().unwrap_err();
+
+        match err.downcast_ref::<InitError>() {
+            Some(InitError::InvalidConfig{..}) => {},
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    let mac = MacAddr::random();
+    let octets = mac.to_string();
+    let first = u8::from_str_radix(&octets[..2], 16).unwrap();
+
+    assert_eq!(first & 0x03, 0x02);
+    assert_eq!(octets.parse::<MacAddr>().unwrap(), mac);
+}
+
+#[test]
+fn bridge_interface() {
+    let net = BridgeInterface::new("net0", "br0")
+        .set_mac_addr("52:54:00:12:34:56".parse().unwrap());
+
+    assert_eq!(net.into_arguments(), vec![
+        "-netdev", "bridge,id=net0,br=br0",
+        "-device", "virtio-net,netdev=net0,mac=52:54:00:12:34:56",
+    ]);
+
+    let net = BridgeInterface::new("net1", "br1")
+        .set_helper("/usr/lib/qemu/qemu-bridge-helper");
+
+    assert_eq!(net.into_arguments(), vec![
+        "-netdev", "bridge,id=net1,br=br1,helper=/usr/lib/qemu/qemu-bridge-helper",
+        "-device", "virtio-net,netdev=net1",
+    ]);
+}
+
+#[test]
+fn capture_output() {
+    let mut instance = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["--bogus-flag"]))
+        .capture_stderr()
+        .start().unwrap();
+
+    assert!(instance.take_stdout().is_none());
+    assert!(instance.read_stderr_to_string().unwrap().contains("bogus-flag"));
+    assert!(!instance.wait().unwrap().exit_status().success());
+
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "echo booted"]))
+        .capture_stdout()
+        .start().unwrap();
+
+    let mut stdout = String::new();
+    instance.take_stdout().unwrap().read_to_string(&mut stdout).unwrap();
+
+    assert_eq!(stdout, "booted\n");
+    assert!(instance.wait().unwrap().exit_status().success());
+}
+
+#[test]
+fn memory_backend() {
+    let memory = Memory::new(1024)
+        .set_mem_path("/dev/hugepages")
+        .set_prealloc(true)
+        .set_share(true);
+
+    assert_eq!(memory.into_arguments(), vec![
+        "-m", "size=1024",
+        "-object", "memory-backend-file,id=mem,size=1024M,mem-path=/dev/hugepages,prealloc=on,share=on",
+        "-machine", "memory-backend=mem",
+    ]);
+
+    let memory = Memory::new(1024)
+        .set_mem_path("/dev/hugepages")
+        .set_prealloc(true)
+        .use_legacy_mem_path(true);
+
+    assert_eq!(memory.into_arguments(), vec!["-m", "size=1024", "-mem-path", "/dev/hugepages", "-mem-prealloc"]);
+}
+
+#[test]
+fn numa() {
+    let numa = Numa::new(vec![NumaNode::new(0..=1, 512), NumaNode::new(2..=3, 512)]).unwrap();
+
+    assert_eq!(numa.into_arguments(), vec![
+        "-numa", "node,nodeid=0,cpus=0-1,mem=512",
+        "-numa", "node,nodeid=1,cpus=2-3,mem=512",
+    ]);
+
+    let numa = Numa::new(vec![NumaNode::new(0..=0, 1024)]).unwrap();
+    assert_eq!(numa.into_arguments(), vec!["-numa", "node,nodeid=0,cpus=0,mem=1024"]);
+
+    assert!(Numa::new(vec![NumaNode::new(0..=2, 512), NumaNode::new(2..=3, 512)]).is_err());
+    assert!(Numa::new(Vec::new()).is_err());
+}
+
+#[test]
+fn arch() {
+    assert_eq!(Arch::Aarch64.executable(), "qemu-system-aarch64");
+    assert_eq!(Arch::X86_64.executable(), "qemu-system-x86_64");
+
+    // Only fails when the executable is really missing.
+    if let Err(err) = Builder::for_arch(Arch::Riscv64) {
+        match err.downcast_ref::<InitError>() {
+            Some(InitError::ExecutableNotFound{exec}) => assert_eq!(exec, "qemu-system-riscv64"),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn image_snapshots_unsupported() {
+    let image = Image::new("/var/lib/vms/disk.img", Format::Raw, 1024 * 1024);
+    let err = image.create_snapshot("snap0").unwrap_err();
+
+    match err.downcast_ref::<ImageError>() {
+        Some(ImageError::Unsupported{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn image_snapshots_parsing() {
+    // qemu-img 2.x, without the icount field.
+    let old = json!({
+        "virtual-size": 1048576,
+        "filename": "disk.qcow2",
+        "format": "qcow2",
+        "snapshots": [
+            {"vm-clock-nsec": 0, "name": "before upgrade", "date-sec": 1514764800, "date-nsec": 12345,
+                "vm-clock-sec": 0, "id": "1", "vm-state-size": 0},
+        ],
+    });
+
+    assert_eq!(image::parse_snapshots(&old).unwrap(), vec![Snapshot {
+        id: String::from("1"),
+        tag: String::from("before upgrade"),
+        vm_size: 0,
+        date: 1514764800,
+    }]);
+
+    // qemu-img 8.x, for a snapshot including the VM state.
+    let new = json!({
+        "virtual-size": 1048576,
+        "filename": "disk.qcow2",
+        "format": "qcow2",
+        "snapshots": [
+            {"icount": 0, "vm-clock-nsec": 0, "name": "snap0", "date-sec": 1700000000, "date-nsec": 0,
+                "vm-clock-sec": 0, "id": "1", "vm-state-size": 0},
+            {"icount": 1234, "vm-clock-nsec": 500, "name": "running", "date-sec": 1700000100, "date-nsec": 0,
+                "vm-clock-sec": 42, "id": "2", "vm-state-size": 67108864},
+        ],
+    });
+
+    let snapshots = image::parse_snapshots(&new).unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[1].tag, "running");
+    assert_eq!(snapshots[1].vm_size, 64 * 1024 * 1024);
+
+    // Images without snapshots have no snapshots array at all.
+    assert!(image::parse_snapshots(&json!({"format": "qcow2"})).unwrap().is_empty());
+
+    assert!(image::parse_snapshots(&json!({"snapshots": [{"id": "1"}]})).is_err());
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_snapshots() {
+    let path = temp_path("snapshots.qcow2");
+
+    let image = Image::new(&path, Format::Qcow2, 1024 * 1024);
+    image.create().unwrap();
+
+    image.create_snapshot("before upgrade").unwrap();
+    image.create_snapshot("after-upgrade").unwrap();
+
+    let snapshots = image.list_snapshots().unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[0].tag, "before upgrade");
+    assert_eq!(snapshots[1].tag, "after-upgrade");
+
+    image.apply_snapshot("before upgrade").unwrap();
+    image.delete_snapshot("after-upgrade").unwrap();
+
+    let snapshots = image.list_snapshots().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].tag, "before upgrade");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_check() {
+    let path = temp_path("check.qcow2");
+
+    Image::new(&path, Format::Qcow2, 1024 * 1024).create().unwrap();
+
+    let report = Image::check(&path).unwrap();
+    assert_eq!(report.corruptions, 0);
+    assert_eq!(report.leaks, 0);
+    assert_eq!(report.errors, 0);
+    assert!(report.image_end_offset.is_some());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[ignore] // Requires qemu-img.
+fn image_encrypted() {
+    let path = temp_path("encrypted.qcow2");
+
+    let mut image = Image::new(&path, Format::Qcow2, 1024 * 1024);
+    image.create_encrypted("correct horse battery staple").unwrap();
+    assert!(image.is_encrypted());
+
+    assert!(Image::info(&path).unwrap().is_encrypted());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn image_encrypted_unsupported() {
+    let mut image = Image::new("/var/lib/vms/disk.img", Format::Raw, 1024 * 1024);
+    let err = image.create_encrypted("secret").unwrap_err();
+
+    match err.downcast_ref::<ImageError>() {
+        Some(ImageError::Unsupported{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn rtc() {
+    assert_eq!(Rtc::new().into_arguments(), vec!["-rtc", "base=utc"]);
+
+    let rtc = Rtc::new().set_clock(RtcClock::Host);
+    assert_eq!(rtc.into_arguments(), vec!["-rtc", "base=utc,clock=host"]);
+
+    let rtc = Rtc::new()
+        .set_base(RtcBase::Localtime)
+        .set_drift_fix(true);
+
+    assert_eq!(rtc.into_arguments(), vec!["-rtc", "base=localtime,driftfix=slew"]);
+
+    let rtc = Rtc::new().set_base(RtcBase::Date(String::from("2006-06-17T16:01:21")));
+    assert_eq!(rtc.into_arguments(), vec!["-rtc", "base=2006-06-17T16:01:21"]);
+}
+
+#[test]
+fn snapshot() {
+    let builder = Builder::new("sh").unwrap()
+        .snapshot()
+        .set(Drive::new("/var/lib/vms/disk.qcow2").set_snapshot(true))
+        .snapshot();
+
+    let args = builder.arguments();
+
+    assert_eq!(args.iter().filter(|arg| *arg == "-snapshot").count(), 1);
+    assert_eq!(args, vec!["-snapshot", "-drive", "file=/var/lib/vms/disk.qcow2,snapshot=on"]);
+}
+
+#[test]
+fn shutdown() {
+    let script = "trap 'exit 0' TERM; while true; do sleep 0.01; done";
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", script]))
+        .start()
+        .unwrap();
+
+    // Leave the shell some time to install its trap.
+    thread::sleep(Duration::from_millis(100));
+
+    let status = instance.shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(status.exit_status().code(), Some(0));
+}
+
+#[test]
+fn shutdown_timeout() {
+    let script = "trap '' TERM; while true; do sleep 0.01; done";
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", script]))
+        .start()
+        .unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    let status = instance.shutdown(Duration::from_millis(200)).unwrap();
+    assert_eq!(status.exit_status().code(), None);
+}
+
+#[test]
+fn shutdown_qmp_busy() {
+    // A QMP server already serving another client never greets new ones.
+    let path = temp_path("busy.sock");
+    let _listener = UnixListener::bind(&path).unwrap();
+
+    let script = "trap 'exit 0' TERM; while true; do sleep 0.01; done";
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", script]))
+        .qmp(&path)
+        .start()
+        .unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    // QMP gets half of the time, leaving the rest for the process to handle SIGTERM.
+    let start = Instant::now();
+    let status = instance.shutdown(Duration::from_millis(400)).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert_eq!(status.exit_status().code(), Some(0));
+
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "exit 0"]))
+        .qmp(&path)
+        .start()
+        .unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    // The process already exited, QMP isn't even tried.
+    let start = Instant::now();
+    assert!(instance.shutdown(Duration::from_secs(10)).unwrap().exit_status().success());
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn escape() {
+    assert_eq!(super::escape("My,VM"), "My,,VM");
+    assert_eq!(super::escape("a=b"), "a=b");
+
+    assert_eq!(Name::new("My,VM").into_arguments(), vec!["-name", "guest=My,,VM"]);
+    assert_eq!(Name::new("a=b").into_arguments(), vec!["-name", "guest=a=b"]);
+
+    let builder = Builder::new("sh").unwrap()
+        .set(Name::new("My,VM"))
+        .debug_threads(true);
+
+    assert_eq!(builder.arguments(), vec!["-name", "guest=My,,VM,debug-threads=on"]);
+
+    let builder = Builder::new("sh").unwrap()
+        .qmp("/run/vm,0/qmp.sock")
+        .set(Monitor::new("/run/vm,0/monitor.sock"))
+        .set(Serial::UnixSocket(PathBuf::from("/run/vm,0/serial.sock")))
+        .set(BridgeInterface::new("net0", "br0").set_helper("/opt/qemu,8/qemu-bridge-helper"));
+
+    assert_eq!(builder.arguments(), vec![
+        "-qmp", "unix:/run/vm,,0/qmp.sock,server,nowait",
+        "-monitor", "unix:/run/vm,,0/monitor.sock,server,nowait",
+        "-serial", "unix:/run/vm,,0/serial.sock,server,nowait",
+        "-netdev", "bridge,id=net0,br=br0,helper=/opt/qemu,,8/qemu-bridge-helper",
+        "-device", "virtio-net,netdev=net0",
+    ]);
+
+    // Identifiers can't inject options either.
+    let share = SharedFolder::new("src,readonly=off", "/home/user/src")
+        .set_mount_tag("tag,x=y");
+
+    assert_eq!(share.into_arguments(), vec![
+        "-fsdev", "local,id=src,,readonly=off,path=/home/user/src,security_model=mapped-xattr",
+        "-device", "virtio-9p-pci,fsdev=src,,readonly=off,mount_tag=tag,,x=y",
+    ]);
+
+    assert_eq!(UserNetwork::new("net0,x=y").into_arguments(), vec![
+        "-netdev", "user,id=net0,,x=y",
+        "-device", "virtio-net,netdev=net0,,x=y",
+    ]);
+
+    assert_eq!(BridgeInterface::new("net1,x=y", "br0,helper=/tmp/evil").into_arguments(), vec![
+        "-netdev", "bridge,id=net1,,x=y,br=br0,,helper=/tmp/evil",
+        "-device", "virtio-net,netdev=net1,,x=y",
+    ]);
+}
+
+#[test]
+fn host_version() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_path("qemu-system-fake");
+    fs::write(&path, "#!/bin/sh\n\
+        if [ \"$1\" = --version ]; then\n\
+            echo 'QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1.4)'\n\
+            echo 'Copyright (c) 2003-2023 Fabrice Bellard and the QEMU Project developers'\n\
+        else\n\
+            echo 'Supported machines are:'\n\
+            echo 'microvm              microvm (i386)'\n\
+            echo 'pc                   Standard PC (i440FX + PIIX, 1996) (alias of pc-i440fx-8.2)'\n\
+            echo 'q35                  Standard PC (Q35 + ICH9, 2009) (alias of pc-q35-8.2)'\n\
+        fi\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let exec = path.to_str().unwrap();
+    let version = host::version(exec).unwrap();
+    let types = host::machine_types(exec).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(version, Version::new(8, 2, 2));
+    assert!(version >= Version::new(2, 4, 0));
+    assert_eq!(types, vec!["microvm", "pc", "q35"]);
+
+    let err = host::version("qemu-rs-missing-executable").unwrap_err();
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::ExecutableNotFound{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn balloon() {
+    assert_eq!(Balloon::new().into_arguments(), vec!["-device", "virtio-balloon"]);
+    assert_eq!(Balloon::new().set_deflate_on_oom(true).into_arguments(), vec!["-device", "virtio-balloon,deflate-on-oom=on"]);
+
+    let path = temp_path("balloon.sock");
+    let server = fake_server(&path, "{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n", vec![
+        "{\"return\": {}}\n",
+        "{\"return\": {}}\n",
+    ]);
+
+    let mut client = QmpClient::connect(&path).unwrap();
+    client.set_balloon_target(512).unwrap();
+
+    assert_eq!(server.join().unwrap(), vec![
+        "{\"execute\":\"qmp_capabilities\"}",
+        "{\"arguments\":{\"value\":536870912},\"execute\":\"balloon\"}",
+    ]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn startup_check_qmp() {
+    let path = temp_path("startup.sock");
+    let server = fake_server(&path, "{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n", vec![
+        "{\"return\": {}}\n",
+        "{\"return\": {\"status\": \"running\", \"running\": true}}\n",
+    ]);
+
+    let mut instance = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["10"]))
+        .startup_check(StartupCheck::QmpReady(path.clone(), Duration::from_secs(5)))
+        .start().unwrap();
+
+    assert_eq!(server.join().unwrap(), vec![
+        "{\"execute\":\"qmp_capabilities\"}",
+        "{\"execute\":\"query-status\"}",
+    ]);
+
+    instance.kill().unwrap();
+    instance.wait().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn startup_check_qmp_unresponsive() {
+    // The socket accepts connections, but nothing ever answers.
+    let path = temp_path("unresponsive.sock");
+    let _listener = UnixListener::bind(&path).unwrap();
+
+    let start = Instant::now();
+    let err = Builder::new("sleep").unwrap()
+        .set(Raw(vec!["10"]))
+        .startup_check(StartupCheck::QmpReady(path.clone(), Duration::from_millis(200)))
+        .start().err().unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::NotReady{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn startup_check_serial() {
+    let mut instance = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "echo 'Booting...'; echo 'debian login: '; echo 'after'; sleep 10"]))
+        .capture_stdout()
+        .startup_check(StartupCheck::SerialContains(String::from("login:"), Duration::from_secs(5)))
+        .start().unwrap();
+
+    assert!(instance.take_stdout().is_some());
+    instance.kill().unwrap();
+    instance.wait().unwrap();
+
+    let start = Instant::now();
+    let err = Builder::new("sh").unwrap()
+        .set(Raw(vec!["-c", "echo 'Booting...'; sleep 10"]))
+        .capture_stdout()
+        .startup_check(StartupCheck::SerialContains(String::from("login:"), Duration::from_millis(100)))
+        .start().err().unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::NotReady{timeout}) => assert_eq!(*timeout, Duration::from_millis(100)),
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn audio() {
+    let audio = Audio::new(audio::Backend::PulseAudio, Card::Hda);
+
+    assert_eq!(audio.into_arguments(), vec![
+        "-audiodev", "pa,id=snd0",
+        "-device", "intel-hda",
+        "-device", "hda-duplex,audiodev=snd0",
+    ]);
+
+    assert_eq!(Audio::new(audio::Backend::None, Card::Ac97).into_arguments(), vec![
+        "-audiodev", "none,id=snd0",
+        "-device", "AC97,audiodev=snd0",
+    ]);
+
+    let err = Builder::new("sh").unwrap()
+        .set(Audio::new(audio::Backend::Spice, Card::Hda))
+        .set(Display::Sdl)
+        .start().err().unwrap();
+
+    match err.downcast_ref::<InitError>() {
+        Some(InitError::InvalidConfig{..}) => {},
+        _ => panic!("unexpected error: {}", err),
+    }
+}
@@ -0,0 +1,114 @@
+//! Wall-clock limit enforcement for running instances.
+
+use libc;
+
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, RecvTimeoutError};
+
+/// Kills a process if it is still running once a time limit is reached.
+pub struct Watchdog {
+    cancel: Sender<()>,
+    state: Arc<Mutex<State>>,
+}
+
+/// State shared with the watchdog thread. Its lock is held while killing the process, so that it
+/// can't be reaped, and its pid reused, in the meantime.
+struct State {
+    cancelled: bool,
+    fired: bool,
+}
+
+impl Watchdog {
+    /// Start watching the process identified by `pid`.
+    pub fn start(pid: u32, limit: Duration) -> Watchdog {
+        let (cancel, cancelled) = mpsc::channel();
+        let state = Arc::new(Mutex::new(State {
+            cancelled: false,
+            fired: false,
+        }));
+        let deadline = Instant::now() + limit;
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            match cancelled.recv_timeout(limit) {
+                Ok(()) => return,
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The instance was dropped without being waited on, keep enforcing the limit.
+                    let now = Instant::now();
+                    if deadline > now {
+                        thread::sleep(deadline - now);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+            }
+
+            let mut state = match shared.lock() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+
+            // The process may have exited before the limit without having been reaped yet.
+            if !state.cancelled && is_running(pid) {
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+
+                state.fired = true;
+            }
+        });
+
+        Watchdog {
+            cancel,
+            state,
+        }
+    }
+
+    /// Stop watching the process, returning wether the time limit was reached and the process
+    /// killed. Must be called once the process has exited, but before it is reaped.
+    pub fn cancel(&self) -> bool {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        state.cancelled = true;
+        let _ = self.cancel.send(());
+
+        state.fired
+    }
+}
+
+/// Check wether the child process identified by `pid` is still running, without reaping it. A
+/// process which has exited, or which isn't a child anymore because it was reaped, isn't running.
+pub fn is_running(pid: u32) -> bool {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let options = libc::WEXITED | libc::WNOWAIT | libc::WNOHANG;
+
+    if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, options) } != 0 {
+        return false;
+    }
+
+    // With `WNOHANG`, the pid is only filled in if the process has exited.
+    unsafe { info.si_pid() == 0 }
+}
+
+/// Block until the child process identified by `pid` exits, without reaping it.
+pub fn wait_exited(pid: u32) -> io::Result<()> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let options = libc::WEXITED | libc::WNOWAIT;
+
+    loop {
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, options) } == 0 {
+            return Ok(());
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}